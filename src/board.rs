@@ -1,11 +1,14 @@
-use shogi::{Position, Piece, Square};
+use shogi::{Move, Position, Piece, Square};
 use crate::PieceButton;
+use crate::rules;
 
 pub struct Board<'a> {
     pub piece_buttons: [[PieceButton<'a>; 9]; 9], 
     pub active: [i32; 2],
     pub active_hand: usize, // 0 - 13 representing piece types
     pub active_moves: [[bool; 9]; 9],
+    pub legal_moves: Vec<Move>, // Legal moves of the active piece, drives both the highlights and click handling
+    pub promotion: Option<(Move, Move)>, // Promoting and non-promoting move waiting for the player's choice
 }
 
 impl<'a> Board<'a> {
@@ -19,6 +22,8 @@ impl<'a> Board<'a> {
             active: [-1, -1],
             active_hand: usize::MAX,
             active_moves: [[false; 9]; 9],
+            legal_moves: Vec::new(),
+            promotion: None,
         }
     }
 
@@ -35,28 +40,34 @@ impl<'a> Board<'a> {
         self.active_hand = i;
     }
 
+    // Keeps the legal moves of the piece on sq, or of the piece in hand when sq is None, and marks their targets.
+    // Moves come from rules::legal_moves, so moves leaving the king in check (pinned pieces included),
+    // nifu, uchifuzume and pieces that could never move again are left out
     pub fn set_active_moves(&mut self, pos: &Position, sq: Option<Square>, p: Piece) {
         self.active_moves = [[false; 9]; 9];
+        self.legal_moves = rules::legal_moves(pos).into_iter().filter(|m| match (*m, sq) {
+            (Move::Normal { from, .. }, Some(sq))  => from == sq,
+            (Move::Drop { piece_type, .. }, None) => piece_type == p.piece_type,
+            _                                     => false,
+        }).collect();
 
-        // Drop move when the square of the piece is None
-        if sq.is_none() {
-            self.drop_candidates(pos, p);
-        }
-        // Normal moves from Bitboard
-        else {
-            let moves = pos.move_candidates(sq.unwrap(), p);
-            for sq in moves {
-                let rank = 8 - (sq.index() / 9); 
-                let file = sq.index() % 9;
-                self.active_moves[rank][file] = true;
-            }
+        let targets: Vec<Square> = self.legal_moves.iter().map(|m| rules::destination(*m)).collect();
+        for sq in targets {
+            self.mark_move(sq);
         }
     }
 
+    // Legal moves of the active piece to the square, two when promotion is optional
+    pub fn legal_moves_to(&self, to: Square) -> Vec<Move> {
+        self.legal_moves.iter().copied().filter(|m| rules::destination(*m) == to).collect()
+    }
+
     pub fn reset_activity(&mut self) {
         self.set_active(-1, -1);
         self.set_active_hand(usize::MAX);
         self.active_moves = [[false; 9]; 9];
+        self.legal_moves.clear();
+        self.promotion = None;
     }
 
     pub fn update_board(&mut self, pos: &Position) {
@@ -73,44 +84,8 @@ impl<'a> Board<'a> {
         }
     }
     
-    // TODO: Find potential drop moves
-    pub fn drop_candidates(&mut self, pos: &Position, p: Piece) {
-        // If pawn, can drop in any unoccupied square in a file without pawn
-        if p.piece_type == shogi::PieceType::Pawn {
-            let mut pawn_files = [false; 9];
-            for rank in 0..9 {
-                for file in 0..9 {
-                    let sq = Square::new(file, rank).unwrap();
-                    if let Some(piece) = pos.piece_at(sq) {
-                        if piece.piece_type == shogi::PieceType::Pawn && piece.color == pos.side_to_move() {
-                            pawn_files[file as usize] = true;
-                        }
-                    }
-                }
-            }
-            for rank in 0..9 {
-                for file in 0..9 {
-                    let sq = Square::new(file, rank).unwrap();
-                    if !pawn_files[file as usize] && pos.piece_at(sq).is_none() {
-                        let r = 8 - (sq.index() / 9); 
-                        let f = sq.index() % 9;
-                        self.active_moves[r][f] = true;
-                    }
-                }
-            }
-        }
-        // Other pieces can drop in any unoccupied square
-        else {
-            for rank in 0..9 {
-                for file in 0..9 {
-                    let sq = Square::new(file, rank).unwrap();
-                    if pos.piece_at(sq).is_none() {
-                        let r = 8 - (sq.index() / 9); 
-                        let f = sq.index() % 9;
-                        self.active_moves[r][f] = true;
-                    }
-                }
-            }
-        }
+    // active_moves is indexed by column from the left (file 9 first) and then row from the top
+    fn mark_move(&mut self, sq: Square) {
+        self.active_moves[8 - sq.file() as usize][sq.rank() as usize] = true;
     }
-}
\ No newline at end of file
+}
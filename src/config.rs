@@ -0,0 +1,148 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+pub const DEFAULT_CONFIG_PATH: &str = "shogi.cfg";
+
+const USAGE: &str = "Usage: Shogi [--config <file>] [--engine <path>] [--engine-dir <dir>] [--engine-args <args>] [--no-engine]
+             [--engine2 <path>] [--engine2-dir <dir>] [--engine2-args <args>]";
+
+// How to launch a USI engine. An empty path means no engine (human vs human only).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EngineConfig {
+    pub path: String,
+    pub working_dir: String,
+    pub args: String, // Whitespace separated
+}
+
+impl EngineConfig {
+    // apery_rust checked out next to the GUI, see README
+    pub fn apery() -> Self {
+        Self {
+            path: String::from("./target/release/apery"),
+            working_dir: String::from("apery_rust"),
+            args: String::new(),
+        }
+    }
+
+    pub fn is_configured(&self) -> bool {
+        !self.path.trim().is_empty()
+    }
+}
+
+// Settings read from shogi.cfg, one "key = value" per line, '#' starts a comment
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub path: PathBuf,
+    pub engines: [EngineConfig; 2], // The second engine is the opponent in engine vs engine games
+}
+
+impl Config {
+    // Falls back to apery_rust when the file does not exist
+    pub fn load(path: PathBuf) -> Result<Self, String> {
+        let mut config = Self { path, engines: [EngineConfig::apery(), EngineConfig::default()] };
+
+        let text = match fs::read_to_string(&config.path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(config),
+            Err(err) => return Err(format!("Failed to read {}: {}", config.path.display(), err)),
+        };
+
+        // Engines the file configures start from scratch, the others keep their defaults
+        let mut configured = [false; 2];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(format!("{}:{}: expected key = value", config.path.display(), i + 1));
+            };
+            if let Some(slot) = Self::slot(key.trim()).filter(|slot| !configured[*slot]) {
+                config.engines[slot] = EngineConfig::default();
+                configured[slot] = true;
+            }
+            if !config.set(key.trim(), value.trim().to_string()) {
+                return Err(format!("{}:{}: unknown setting {}", config.path.display(), i + 1, key.trim()));
+            }
+        }
+
+        Ok(config)
+    }
+
+    // Command line flags override the config file
+    pub fn from_args(args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let args: Vec<String> = args.collect();
+
+        let mut config_path = PathBuf::from(DEFAULT_CONFIG_PATH);
+        let mut overrides: Vec<(&str, String)> = Vec::new();
+
+        let mut i = 0;
+        while i < args.len() {
+            let flag = args[i].as_str();
+            match flag {
+                "--no-engine" => overrides.push(("engine.path", String::new())),
+                "--help" | "-h" => return Err(USAGE.to_string()),
+                "--config" | "--engine" | "--engine-dir" | "--engine-args" | "--engine2" | "--engine2-dir" | "--engine2-args" => {
+                    i += 1;
+                    let value = args.get(i).ok_or(format!("Missing value for {}\n{}", flag, USAGE))?.clone();
+                    match flag {
+                        "--config"      => config_path = PathBuf::from(value),
+                        "--engine"      => overrides.push(("engine.path", value)),
+                        "--engine-dir"  => overrides.push(("engine.dir", value)),
+                        "--engine-args" => overrides.push(("engine.args", value)),
+                        "--engine2"     => overrides.push(("engine2.path", value)),
+                        "--engine2-dir" => overrides.push(("engine2.dir", value)),
+                        _               => overrides.push(("engine2.args", value)),
+                    }
+                }
+                _ => return Err(format!("Unknown argument {}\n{}", flag, USAGE)),
+            }
+            i += 1;
+        }
+
+        let mut config = Self::load(config_path)?;
+        for (key, value) in overrides {
+            // A different engine should not inherit apery's working directory
+            if key == "engine.path" && config.engines[0] == EngineConfig::apery() {
+                config.engines[0] = EngineConfig::default();
+            }
+            config.set(key, value);
+        }
+
+        Ok(config)
+    }
+
+    // engine.* configures the first engine, engine2.* the second
+    fn slot(key: &str) -> Option<usize> {
+        match key.split_once('.')?.0 {
+            "engine"  => Some(0),
+            "engine2" => Some(1),
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: String) -> bool {
+        let (Some(slot), Some((_, field))) = (Self::slot(key), key.split_once('.')) else {
+            return false;
+        };
+        let engine = &mut self.engines[slot];
+        match field {
+            "path" => engine.path = value,
+            "dir"  => engine.working_dir = value,
+            "args" => engine.args = value,
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut text = String::from("# Shogi GUI settings\n");
+        for (prefix, engine) in ["engine", "engine2"].iter().zip(self.engines.iter()) {
+            text.push_str(&format!("{}.path = {}\n", prefix, engine.path));
+            text.push_str(&format!("{}.dir = {}\n", prefix, engine.working_dir));
+            text.push_str(&format!("{}.args = {}\n", prefix, engine.args));
+        }
+        fs::write(&self.path, text)
+    }
+}
@@ -0,0 +1,523 @@
+// CSA standard game records, used by floodgate, the CSA server protocol and many engine tools
+// http://www2.computer-shogi.org/protocol/record_v22.html
+
+use shogi::{Color, Move, Piece, PieceType, Position, Square};
+
+use crate::record::{GameEnd, GameRecord, STARTPOS_SFEN};
+use crate::rules;
+
+// KIF header keys stored in the record and their CSA equivalents
+const HEADER_KEYS: [(&str, &str); 8] = [
+    ("先手", "N+"),
+    ("後手", "N-"),
+    ("開始日時", "$START_TIME"),
+    ("終了日時", "$END_TIME"),
+    ("棋戦", "$EVENT"),
+    ("場所", "$SITE"),
+    ("持ち時間", "$TIME_LIMIT"),
+    ("戦型", "$OPENING"),
+];
+
+const PIECE_CODES: [(&str, PieceType); 14] = [
+    ("FU", PieceType::Pawn), ("KY", PieceType::Lance), ("KE", PieceType::Knight), ("GI", PieceType::Silver),
+    ("KI", PieceType::Gold), ("KA", PieceType::Bishop), ("HI", PieceType::Rook), ("OU", PieceType::King),
+    ("TO", PieceType::ProPawn), ("NY", PieceType::ProLance), ("NK", PieceType::ProKnight), ("NG", PieceType::ProSilver),
+    ("UM", PieceType::ProBishop), ("RY", PieceType::ProRook),
+];
+
+// Pieces of both sides in a full set, for "00AL"
+const PIECE_COUNTS: [(PieceType, u8); 7] = [
+    (PieceType::Rook, 2), (PieceType::Bishop, 2), (PieceType::Gold, 4), (PieceType::Silver, 4),
+    (PieceType::Knight, 4), (PieceType::Lance, 4), (PieceType::Pawn, 18),
+];
+
+pub fn piece_code(piece_type: PieceType) -> &'static str {
+    PIECE_CODES.iter().find(|(_, pt)| *pt == piece_type).map(|(code, _)| *code).unwrap_or("OU")
+}
+
+pub fn parse_piece(code: &str) -> Option<PieceType> {
+    PIECE_CODES.iter().find(|(c, _)| *c == code).map(|(_, pt)| *pt)
+}
+
+fn parse_color(c: char) -> Option<Color> {
+    match c {
+        '+' => Some(Color::Black),
+        '-' => Some(Color::White),
+        _ => None,
+    }
+}
+
+fn color_sign(color: Color) -> char {
+    match color {
+        Color::Black => '+',
+        Color::White => '-',
+    }
+}
+
+// "77" as file and rank, "00" is the hand
+fn parse_square(text: &str) -> Option<Option<Square>> {
+    let mut digits = text.chars().map(|c| c.to_digit(10));
+    let (file, rank) = (digits.next()??, digits.next()??);
+    if file == 0 && rank == 0 {
+        return Some(None);
+    }
+    Some(Some(Square::new(file.checked_sub(1)? as u8, rank.checked_sub(1)? as u8)?))
+}
+
+fn square_code(sq: Square) -> String {
+    format!("{}{}", sq.file() + 1, sq.rank() + 1)
+}
+
+// Start position built from PI, P1..P9 and P+/P- lines
+struct Setup {
+    board: [[Option<Piece>; 9]; 9], // [rank][file], file 0 is the 1 file
+    hands: Vec<(Color, PieceType)>,
+    side: Color,
+}
+
+impl Setup {
+    fn empty() -> Self {
+        Self { board: [[None; 9]; 9], hands: Vec::new(), side: Color::Black }
+    }
+
+    // "PI" optionally followed by pieces to remove, e.g. "PI82HI22KA" for a two piece handicap
+    fn parse_pi(&mut self, rest: &str) -> Option<()> {
+        let pos = rules::position_from_sfen(STARTPOS_SFEN).ok()?;
+        for sq in Square::iter() {
+            self.board[sq.rank() as usize][sq.file() as usize] = *pos.piece_at(sq);
+        }
+        for chunk in rest.as_bytes().chunks(4) {
+            let chunk = std::str::from_utf8(chunk).ok()?;
+            let sq = parse_square(chunk.get(..2)?)??;
+            self.board[sq.rank() as usize][sq.file() as usize] = None;
+        }
+        Some(())
+    }
+
+    // "P1-KY-KE-GI-KI-OU-KI-GI-KE-KY", cells run from the 9 file to the 1 file
+    fn parse_row(&mut self, rank: usize, cells: &str) -> Option<()> {
+        let chars: Vec<char> = cells.chars().collect();
+        for (i, cell) in chars.chunks(3).take(9).enumerate() {
+            let text: String = cell.iter().collect();
+            self.board[rank][8 - i] = match parse_color(cell[0]) {
+                Some(color) => Some(Piece { piece_type: parse_piece(text.get(1..)?)?, color }),
+                None => None,
+            };
+        }
+        Some(())
+    }
+
+    // "P+00HI00KA" puts pieces in hand, "P-5142OU" on the board, "00AL" is every remaining piece
+    fn parse_pieces(&mut self, color: Color, rest: &str) -> Option<()> {
+        for chunk in rest.as_bytes().chunks(4) {
+            let chunk = std::str::from_utf8(chunk).ok()?;
+            let code = chunk.get(2..4)?;
+            match (parse_square(chunk.get(..2)?)?, code) {
+                (None, "AL") => {
+                    for (piece_type, total) in PIECE_COUNTS {
+                        let used = self.board.iter().flatten().flatten()
+                            .filter(|p| p.piece_type == piece_type || p.piece_type.unpromote() == Some(piece_type))
+                            .count()
+                            + self.hands.iter().filter(|(_, pt)| *pt == piece_type).count();
+                        for _ in used..total as usize {
+                            self.hands.push((color, piece_type));
+                        }
+                    }
+                }
+                (None, code) => self.hands.push((color, parse_piece(code)?)),
+                (Some(sq), code) => {
+                    self.board[sq.rank() as usize][sq.file() as usize] = Some(Piece { piece_type: parse_piece(code)?, color });
+                }
+            }
+        }
+        Some(())
+    }
+
+    fn to_sfen(&self) -> String {
+        let rows: Vec<String> = self.board.iter().map(|row| {
+            let mut text = String::new();
+            let mut empty = 0;
+            for cell in row.iter().rev() {
+                match cell {
+                    Some(piece) => {
+                        if empty > 0 {
+                            text.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        text.push_str(&piece.to_string());
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                text.push_str(&empty.to_string());
+            }
+            text
+        }).collect();
+
+        let mut hand = String::new();
+        for color in [Color::Black, Color::White] {
+            for (piece_type, _) in PIECE_COUNTS {
+                let count = self.hands.iter().filter(|h| **h == (color, piece_type)).count();
+                if count > 1 {
+                    hand.push_str(&count.to_string());
+                }
+                if count > 0 {
+                    hand.push_str(&Piece { piece_type, color }.to_string());
+                }
+            }
+        }
+
+        format!(
+            "{} {} {} 1",
+            rows.join("/"),
+            if self.side == Color::Black { "b" } else { "w" },
+            if hand.is_empty() { "-" } else { &hand },
+        )
+    }
+}
+
+// "+7776FU" or "-0055KA", the piece is the one standing on the destination after the move
+fn parse_move(pos: &Position, text: &str) -> Option<Move> {
+    if text.len() != 7 || parse_color(text.chars().next()?)? != pos.side_to_move() {
+        return None;
+    }
+    let to = parse_square(text.get(3..5)?)??;
+    let piece_type = parse_piece(text.get(5..7)?)?;
+    match parse_square(text.get(1..3)?)? {
+        None => Some(Move::Drop { to, piece_type }),
+        Some(from) => {
+            let moved = pos.piece_at(from).as_ref()?;
+            Some(Move::Normal { from, to, promote: moved.piece_type != piece_type })
+        }
+    }
+}
+
+pub fn parse_end(text: &str, side: Color) -> Option<GameEnd> {
+    let end = match text {
+        "%TORYO"       => GameEnd::Resign,
+        "%CHUDAN"      => GameEnd::Interrupt,
+        "%SENNICHITE"  => GameEnd::Sennichite,
+        "%TIME_UP"     => GameEnd::TimeUp,
+        "%ILLEGAL_MOVE" => GameEnd::IllegalLoss,
+        "%JISHOGI" | "%HIKIWAKE" => GameEnd::Jishogi,
+        "%MAX_MOVES"   => GameEnd::MaxMoves,
+        "%KACHI"       => GameEnd::DeclareWin,
+        "%TSUMI"       => GameEnd::Checkmate,
+        // The side that acted illegally loses
+        "%+ILLEGAL_ACTION" | "%-ILLEGAL_ACTION" => {
+            if parse_color(text.chars().nth(1)?)? == side { GameEnd::IllegalLoss } else { GameEnd::IllegalWin }
+        }
+        _ => return None,
+    };
+    Some(end)
+}
+
+pub fn end_code(end: GameEnd, side: Color) -> String {
+    let code = match end {
+        GameEnd::Resign      => "TORYO",
+        GameEnd::Interrupt   => "CHUDAN",
+        GameEnd::Sennichite  => "SENNICHITE",
+        GameEnd::Jishogi     => "JISHOGI",
+        GameEnd::Checkmate   => "TSUMI",
+        GameEnd::TimeUp      => "TIME_UP",
+        GameEnd::IllegalLoss => "ILLEGAL_MOVE",
+        GameEnd::DeclareWin  => "KACHI",
+        GameEnd::MaxMoves    => "MAX_MOVES",
+        GameEnd::IllegalWin  => return format!("%{}ILLEGAL_ACTION", color_sign(side.flip())),
+    };
+    format!("%{}", code)
+}
+
+pub fn parse(text: &str) -> Result<GameRecord, String> {
+    let mut header = Vec::new();
+    let mut setup = Setup::empty();
+    let mut has_setup = false;
+    let mut record: Option<GameRecord> = None;
+    let mut pos = None;
+    let mut comments = Vec::new();
+
+    // Several statements may share a line separated by commas, comments run to the end of the line
+    let statements = text.lines().enumerate().flat_map(|(i, line)| {
+        let line = line.trim_start_matches('\u{feff}').trim_end();
+        let parts: Vec<&str> = if line.starts_with('\'') { vec![line] } else { line.split(',').collect() };
+        parts.into_iter().map(move |part| (i + 1, part.trim()))
+    });
+
+    for (line, statement) in statements {
+        if statement.is_empty() || statement.starts_with('V') {
+            continue;
+        }
+        if let Some(comment) = statement.strip_prefix('\'') {
+            // "'*" lines are comments meant for the record, other ones are notes for the reader
+            if let Some(comment) = comment.strip_prefix('*') {
+                match record.as_mut() {
+                    Some(record) => record.add_comment(comment.to_string()),
+                    None => comments.push(comment.to_string()),
+                }
+            }
+            continue;
+        }
+
+        let Some(record) = record.as_mut() else {
+            // Header and start position
+            if let Some(name) = statement.strip_prefix("N+") {
+                header.push((String::from("先手"), name.to_string()));
+            }
+            else if let Some(name) = statement.strip_prefix("N-") {
+                header.push((String::from("後手"), name.to_string()));
+            }
+            else if let Some(info) = statement.strip_prefix('$') {
+                let (key, value) = info.split_once(':').ok_or(format!("line {}: expected $KEY:value", line))?;
+                let key = format!("${}", key);
+                let kif_key = HEADER_KEYS.iter().find(|(_, csa)| *csa == key).map(|(kif, _)| kif.to_string()).unwrap_or(key);
+                header.push((kif_key, value.to_string()));
+            }
+            else if let Some(rest) = statement.strip_prefix("PI") {
+                setup.parse_pi(rest).ok_or(format!("line {}: invalid PI", line))?;
+                has_setup = true;
+            }
+            else if let Some(rest) = statement.strip_prefix("P+").or(statement.strip_prefix("P-")) {
+                let color = parse_color(statement.chars().nth(1).unwrap_or_default()).unwrap_or(Color::Black);
+                setup.parse_pieces(color, rest).ok_or(format!("line {}: invalid pieces", line))?;
+                has_setup = true;
+            }
+            else if let Some(rest) = statement.strip_prefix('P') {
+                let rank = rest.chars().next().and_then(|c| c.to_digit(10)).filter(|r| (1..=9).contains(r))
+                    .ok_or(format!("line {}: invalid board row", line))?;
+                setup.parse_row(rank as usize - 1, &rest[1..]).ok_or(format!("line {}: invalid board row", line))?;
+                has_setup = true;
+            }
+            // A lone + or - is the side to move and ends the start position
+            else if statement == "+" || statement == "-" {
+                setup.side = parse_color(statement.chars().next().unwrap_or_default()).unwrap_or(Color::Black);
+                let start_sfen = if has_setup { setup.to_sfen() } else { STARTPOS_SFEN.to_string() };
+                pos = Some(rules::position_from_sfen(&start_sfen).map_err(|err| format!("line {}: invalid start position: {}", line, err))?);
+                let mut new_record = GameRecord::new(&start_sfen);
+                new_record.header = std::mem::take(&mut header);
+                new_record.start_comments = std::mem::take(&mut comments);
+                record = Some(new_record);
+            }
+            else {
+                return Err(format!("line {}: unexpected {}", line, statement));
+            }
+            continue;
+        };
+
+        let pos = pos.as_mut().ok_or(format!("line {}: missing start position", line))?;
+        if statement.starts_with('%') {
+            record.end = Some(parse_end(statement, pos.side_to_move()).ok_or(format!("line {}: unknown result {}", line, statement))?);
+            break;
+        }
+        if let Some(secs) = statement.strip_prefix('T') {
+            if let Some(last) = record.last_move_mut() {
+                last.time_secs = secs.parse().ok();
+            }
+            continue;
+        }
+        let m = parse_move(pos, statement).ok_or(format!("line {}: invalid move {}", line, statement))?;
+        rules::make_move(pos, m).map_err(|err| format!("line {}: illegal move {}: {}", line, statement, err))?;
+        record.play(m, None);
+    }
+
+    record.ok_or(String::from("Missing side to move after the start position"))
+}
+
+fn write_setup(pos: &Position, out: &mut String) {
+    for rank in 0..9 {
+        out.push_str(&format!("P{}", rank + 1));
+        for file in (0..9).rev() {
+            match *pos.piece_at(Square::new(file, rank).unwrap()) {
+                Some(piece) => out.push_str(&format!("{}{}", color_sign(piece.color), piece_code(piece.piece_type))),
+                None => out.push_str(" * "),
+            }
+        }
+        out.push('\n');
+    }
+    for color in [Color::Black, Color::White] {
+        let mut hand = String::new();
+        for (piece_type, _) in PIECE_COUNTS {
+            for _ in 0..pos.hand(Piece { piece_type, color }) {
+                hand.push_str(&format!("00{}", piece_code(piece_type)));
+            }
+        }
+        if !hand.is_empty() {
+            out.push_str(&format!("P{}{}\n", color_sign(color), hand));
+        }
+    }
+}
+
+// CSA has no variations, only the main line is written
+pub fn write(record: &GameRecord) -> String {
+    let mut out = String::from("V2.2\n");
+    for (key, value) in &record.header {
+        match HEADER_KEYS.iter().find(|(kif, _)| kif == key) {
+            Some((_, csa)) if csa.starts_with('N') => out.push_str(&format!("{}{}\n", csa, value)),
+            Some((_, csa)) => out.push_str(&format!("{}:{}\n", csa, value)),
+            None if key.starts_with('$') => out.push_str(&format!("{}:{}\n", key, value)),
+            None => out.push_str(&format!("'{}：{}\n", key, value)),
+        }
+    }
+
+    let Ok(mut pos) = rules::position_from_sfen(&record.start_sfen) else {
+        return out;
+    };
+    if record.start_sfen == STARTPOS_SFEN {
+        out.push_str("PI\n");
+    }
+    else {
+        write_setup(&pos, &mut out);
+    }
+    out.push_str(&format!("{}\n", color_sign(pos.side_to_move())));
+    for comment in &record.start_comments {
+        out.push_str(&format!("'*{}\n", comment));
+    }
+
+    for rm in &record.moves {
+        let (from, to, piece_type) = match rm.mv {
+            Move::Normal { from, to, promote } => {
+                let piece_type = pos.piece_at(from).map(|p| p.piece_type).unwrap_or(PieceType::King);
+                let piece_type = if promote { piece_type.promote().unwrap_or(piece_type) } else { piece_type };
+                (square_code(from), to, piece_type)
+            }
+            Move::Drop { to, piece_type } => (String::from("00"), to, piece_type),
+        };
+        out.push_str(&format!("{}{}{}{}\n", color_sign(pos.side_to_move()), from, square_code(to), piece_code(piece_type)));
+        if let Some(secs) = rm.time_secs {
+            out.push_str(&format!("T{}\n", secs));
+        }
+        for comment in rm.comment_lines() {
+            out.push_str(&format!("'*{}\n", comment));
+        }
+        if rules::make_move(&mut pos, rm.mv).is_err() {
+            break;
+        }
+    }
+
+    if let Some(end) = record.end {
+        out.push_str(&format!("{}\n", end_code(end, pos.side_to_move())));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::RecordMove;
+
+    fn usi(moves: &[RecordMove]) -> Vec<String> {
+        moves.iter().map(|rm| rm.mv.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_pi_handicap_with_times() {
+        shogi::bitboard::Factory::init();
+        let record = parse("\
+V2.2
+N+下手花子
+N-上手太郎
+$EVENT:練習対局
+PI82HI22KA
+-
+-3334FU
+T3
++7776FU
+T12
+'*角道を開ける
+%TORYO
+").unwrap();
+
+        assert_eq!(record.header_value("先手"), Some("下手花子"));
+        assert_eq!(record.header_value("後手"), Some("上手太郎"));
+        assert_eq!(record.header_value("棋戦"), Some("練習対局"));
+        assert_eq!(record.start_sfen, "lnsgkgsnl/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1");
+        assert_eq!(usi(&record.moves), ["3c3d", "7g7f"]);
+        assert_eq!(record.moves[0].time_secs, Some(3));
+        assert_eq!(record.moves[1].time_secs, Some(12));
+        assert_eq!(record.moves[1].comments, vec![String::from("角道を開ける")]);
+        // The bishop removed by PI cannot move
+        assert!(parse("PI82HI22KA\n-\n-3334FU\n+7776FU\n-2277KA\n").is_err());
+        assert_eq!(record.end, Some(GameEnd::Resign));
+    }
+
+    #[test]
+    fn parses_board_rows_and_hands() {
+        shogi::bitboard::Factory::init();
+        let record = parse("\
+P1 *  *  *  *  *  *  *  * -OU
+P2 *  *  *  *  *  *  *  *  * 
+P3 *  *  *  *  *  *  * +TO * 
+P4 *  *  *  *  *  *  *  *  * 
+P5 *  *  *  *  *  *  *  *  * 
+P6 *  *  *  *  *  *  *  *  * 
+P7 *  *  *  *  *  *  *  *  * 
+P8 *  *  *  *  *  *  *  *  * 
+P9+OU *  *  *  *  *  *  *  * 
+P+00KI00FU00FU
+P-00HI
++
++0012KI
+%KACHI
+").unwrap();
+
+        assert_eq!(record.start_sfen, "8k/9/7+P1/9/9/9/9/9/K8 b G2Pr 1");
+        assert_eq!(usi(&record.moves), ["G*1b"]);
+        assert_eq!(record.end, Some(GameEnd::DeclareWin));
+    }
+
+    #[test]
+    fn parses_pieces_placed_on_the_board_and_the_rest_in_hand() {
+        shogi::bitboard::Factory::init();
+        let record = parse("P-51OU\nP+59OU00KI00KI\nP-00AL\n+\n%SENNICHITE\n").unwrap();
+
+        assert_eq!(record.start_sfen, "4k4/9/9/9/9/9/9/9/4K4 b 2G2r2b2g4s4n4l18p 1");
+        assert!(record.moves.is_empty());
+        assert_eq!(record.end, Some(GameEnd::Sennichite));
+    }
+
+    #[test]
+    fn parses_end_codes() {
+        assert_eq!(parse_end("%TORYO", Color::Black), Some(GameEnd::Resign));
+        assert_eq!(parse_end("%SENNICHITE", Color::White), Some(GameEnd::Sennichite));
+        assert_eq!(parse_end("%KACHI", Color::Black), Some(GameEnd::DeclareWin));
+        assert_eq!(parse_end("%+ILLEGAL_ACTION", Color::Black), Some(GameEnd::IllegalLoss));
+        assert_eq!(parse_end("%+ILLEGAL_ACTION", Color::White), Some(GameEnd::IllegalWin));
+        assert_eq!(parse_end("%RESIGN", Color::Black), None);
+        for end in GameEnd::ALL {
+            assert_eq!(parse_end(&end_code(end, Color::White), Color::White), Some(end));
+        }
+    }
+
+    #[test]
+    fn writes_pi_times_and_end() {
+        shogi::bitboard::Factory::init();
+        let mut record = GameRecord::new(STARTPOS_SFEN);
+        record.set_header("先手", String::from("先手一郎"));
+        record.play(rules::parse_move("7g7f").unwrap(), Some(4));
+        record.play(rules::parse_move("3c3d").unwrap(), None);
+        record.play(rules::parse_move("8h2b+").unwrap(), Some(9));
+        record.end = Some(GameEnd::Resign);
+
+        assert_eq!(write(&record), "V2.2\nN+先手一郎\nPI\n+\n+7776FU\nT4\n-3334FU\n+8822UM\nT9\n%TORYO\n");
+    }
+
+    #[test]
+    fn round_trips_record() {
+        shogi::bitboard::Factory::init();
+        let mut record = GameRecord::new("4k4/9/4P4/9/9/9/9/9/4K4 w GS2Pr2b 1");
+        record.set_header("後手", String::from("後手次郎"));
+        record.set_header("開始日時", String::from("2024/05/01 10:00:00"));
+        record.start_comments = vec![String::from("詰将棋")];
+        record.play(rules::parse_move("5a4b").unwrap(), Some(7));
+        record.add_comment(String::from("逃げる"));
+        record.play(rules::parse_move("G*5b").unwrap(), Some(1));
+        record.play(rules::parse_move("4b3c").unwrap(), None);
+        record.end = Some(GameEnd::Interrupt);
+
+        let text = write(&record);
+        assert!(text.contains("P3 *  *  *  * +FU *  *  *  * \n"));
+        assert!(text.contains("P+00KI00GI00FU00FU\nP-00HI00KA00KA\n-\n"));
+        assert_eq!(parse(&text).unwrap(), record);
+        assert_eq!(write(&parse(&text).unwrap()), text);
+    }
+}
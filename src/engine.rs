@@ -0,0 +1,287 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::EngineConfig;
+use crate::usi::{self, BestMove, SearchInfo, UsiOption};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EngineState {
+    WaitingUsiOk,   // Sent "usi", collecting id and option lines
+    WaitingReadyOk, // Sent "isready" after startup or setoption
+    Ready,
+    Terminated,     // The process exited or closed its output
+}
+
+// USI engine process. Searches run in the background and results are polled from ShogiGame::update.
+pub struct Engine {
+    child: Child,
+    input: ChildStdin,
+    rx: mpsc::Receiver<String>,
+    pub state: EngineState,
+    pub name: String,
+    pub author: String,
+    pub options: Vec<UsiOption>,
+    pub lines: Vec<SearchInfo>, // Latest info of the current or last search, one entry per MultiPV line
+    pub search_sfen: String, // Position of the current or last search, the PV is relative to it
+    new_game_pending: bool, // usinewgame is sent before the first search of each game
+    search_start: Option<Instant>,
+    infinite: bool, // Analysis search, never played on the board
+    stale_bestmoves: usize, // Cancelled searches whose bestmove should be ignored
+}
+
+impl Engine {
+    // Launch the engine described by config, relative paths are resolved from its working directory
+    pub fn spawn(config: &EngineConfig) -> io::Result<Self> {
+        let mut command = Command::new(config.path.trim());
+        command
+            .args(config.args.split_whitespace())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit());
+        if !config.working_dir.trim().is_empty() {
+            command.current_dir(config.working_dir.trim());
+        }
+
+        Ok(Self::new(command.spawn()?))
+    }
+
+    pub fn new(mut child: Child) -> Self {
+        let input  = child.stdin.take().expect("Failed to open stdin");
+        let output = child.stdout.take().expect("Failed to open stdout");
+
+        let (tx, rx) = mpsc::channel::<String>();
+
+        thread::spawn(move || {
+            let reader = BufReader::new(output);
+            for line in reader.lines() {
+                match line {
+                    Ok(output) => {
+                        if let Err(err) = tx.send(output) {
+                            eprintln!("Error sending engine output: {}", err);
+                            break;
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("Error reading engine output: {}", err);
+                        break;
+                    }
+                }
+            }
+        });
+
+        let mut engine = Self {
+            child,
+            input,
+            rx,
+            state: EngineState::WaitingUsiOk,
+            name: String::new(),
+            author: String::new(),
+            options: Vec::new(),
+            lines: Vec::new(),
+            search_sfen: String::new(),
+            new_game_pending: true,
+            search_start: None,
+            infinite: false,
+            stale_bestmoves: 0,
+        };
+        engine.send("usi");
+        engine
+    }
+
+    pub fn send(&mut self, command: &str) {
+        if let Err(err) = writeln!(self.input, "{}", command) {
+            eprintln!("Error writing to engine: {}", err);
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.state == EngineState::Ready
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.state == EngineState::Terminated
+    }
+
+    // Send setoption for every option changed in the options window, then resync with isready
+    pub fn apply_options(&mut self) {
+        if self.is_terminated() {
+            return;
+        }
+        let commands: Vec<String> = self.options.iter()
+            .filter(|option| option.is_modified())
+            .map(|option| option.setoption_command())
+            .collect();
+
+        for command in commands {
+            self.send(&command);
+        }
+        for option in self.options.iter_mut() {
+            option.applied = option.value.clone();
+        }
+        self.send("isready");
+        self.state = EngineState::WaitingReadyOk;
+    }
+
+    // Button options take effect immediately
+    pub fn press_button(&mut self, name: &str) {
+        self.send(&format!("setoption name {}", name));
+    }
+
+    // Change a single option, e.g. MultiPV from the analysis window. Any running search is cancelled.
+    pub fn set_option(&mut self, name: &str, value: &str) -> bool {
+        let Some(option) = self.options.iter_mut().find(|option| option.name.eq_ignore_ascii_case(name)) else {
+            return false;
+        };
+        option.value = value.to_string();
+        self.cancel();
+        self.apply_options();
+        true
+    }
+
+    pub fn option(&self, name: &str) -> Option<&UsiOption> {
+        self.options.iter().find(|option| option.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn new_game(&mut self) {
+        self.cancel();
+        self.new_game_pending = true;
+    }
+
+    // Start searching the given position for a move to play, returns immediately
+    pub fn go(&mut self, sfen: &str, byoyomi_ms: i32) {
+        self.start_search(sfen, &format!("go byoyomi {}", byoyomi_ms));
+        self.infinite = false;
+    }
+
+    // Analyze the given position until cancelled, restarting any running search
+    pub fn go_infinite(&mut self, sfen: &str) {
+        self.start_search(sfen, "go infinite");
+        self.infinite = true;
+    }
+
+    fn start_search(&mut self, sfen: &str, go: &str) {
+        self.cancel();
+        if self.new_game_pending {
+            self.send("usinewgame");
+            self.new_game_pending = false;
+        }
+        self.send(&format!("position sfen {}", sfen));
+        self.send(go);
+        self.search_start = Some(Instant::now());
+        self.search_sfen = sfen.to_string();
+        self.lines.clear();
+    }
+
+    // Ask the engine to finish early, its bestmove will still be returned by poll
+    pub fn stop(&mut self) {
+        if self.is_thinking() {
+            self.send("stop");
+        }
+    }
+
+    // Stop the current search and throw away its result (new game, undo, etc.)
+    pub fn cancel(&mut self) {
+        if self.search_start.is_some() {
+            self.send("stop");
+            self.stale_bestmoves += 1;
+            self.search_start = None;
+            self.infinite = false;
+        }
+    }
+
+    // Searching for a move to play, the board waits for it
+    pub fn is_thinking(&self) -> bool {
+        self.search_start.is_some() && !self.infinite
+    }
+
+    pub fn is_analyzing(&self) -> bool {
+        self.search_start.is_some() && self.infinite
+    }
+
+    // Main line of the current or last search
+    pub fn best_line(&self) -> Option<&SearchInfo> {
+        self.lines.first()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.search_start.map(|start| start.elapsed()).unwrap_or_default()
+    }
+
+    // Drain engine output without blocking. Returns the reply of a finished search.
+    pub fn poll(&mut self) -> Option<BestMove> {
+        loop {
+            let line = match self.rx.try_recv() {
+                Ok(line) => line,
+                Err(TryRecvError::Empty) => break,
+                // The reader thread ends with the engine's output, no search will finish anymore
+                Err(TryRecvError::Disconnected) => {
+                    self.state = EngineState::Terminated;
+                    self.search_start = None;
+                    self.infinite = false;
+                    self.stale_bestmoves = 0;
+                    break;
+                }
+            };
+            if let Some(best_move) = usi::parse_bestmove(&line) {
+                if self.stale_bestmoves > 0 {
+                    self.stale_bestmoves -= 1;
+                    continue;
+                }
+                self.search_start = None;
+                if self.infinite {
+                    // Engines may end an infinite search on their own, e.g. when mated
+                    self.infinite = false;
+                    continue;
+                }
+                if let BestMove::Invalid(line) = &best_move {
+                    eprintln!("Unreadable bestmove from {}: {}", self.name, line);
+                }
+                return Some(best_move);
+            }
+            else if self.state != EngineState::Ready {
+                self.handle_handshake(&line);
+            }
+            else if self.stale_bestmoves == 0 {
+                if let Some(info) = usi::parse_info(&line) {
+                    let index = info.multipv.unwrap_or(1).max(1) - 1;
+                    if self.lines.len() <= index {
+                        self.lines.resize(index + 1, SearchInfo::default());
+                    }
+                    self.lines[index].merge(info);
+                }
+            }
+        }
+        None
+    }
+
+    fn handle_handshake(&mut self, line: &str) {
+        if let Some((key, value)) = usi::parse_id(line) {
+            match key {
+                "name"   => self.name = value.to_string(),
+                "author" => self.author = value.to_string(),
+                _ => (),
+            }
+        }
+        else if let Some(option) = usi::parse_option(line) {
+            self.options.push(option);
+        }
+        else if line == "usiok" {
+            self.send("isready");
+            self.state = EngineState::WaitingReadyOk;
+        }
+        else if line == "readyok" {
+            self.state = EngineState::Ready;
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        self.send("quit");
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
@@ -0,0 +1,397 @@
+// JSON Kifu Format, the game record format of web viewers such as Kifu for JS
+// https://github.com/na2hiro/json-kifu-format
+
+use shogi::{Color, Move, Piece, PieceType, Position, Square};
+
+use crate::csa;
+use crate::json::Json;
+use crate::kif::HANDICAPS;
+use crate::notation;
+use crate::record::{GameEnd, GameRecord, RecordMove, STARTPOS_SFEN};
+use crate::rules;
+
+// Preset names in the same order as kif::HANDICAPS
+const PRESETS: [&str; 11] = ["HIRATE", "KY", "KY_R", "KA", "HI", "HIKY", "2", "4", "6", "8", "10"];
+
+// Hand pieces in JKF order
+const HAND_KINDS: [PieceType; 7] = [
+    PieceType::Pawn, PieceType::Lance, PieceType::Knight, PieceType::Silver, PieceType::Gold, PieceType::Bishop, PieceType::Rook,
+];
+
+fn color_number(color: Color) -> Json {
+    Json::Number(color.index() as f64)
+}
+
+fn object(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+}
+
+// {"x": 7, "y": 6} with x the file and y the rank, both from 1
+fn place(sq: Square) -> Json {
+    object(vec![("x", Json::Number(sq.file() as f64 + 1.0)), ("y", Json::Number(sq.rank() as f64 + 1.0))])
+}
+
+fn parse_place(json: &Json) -> Option<Square> {
+    let x = json.get("x")?.as_i64()?;
+    let y = json.get("y")?.as_i64()?;
+    Square::new(u8::try_from(x - 1).ok()?, u8::try_from(y - 1).ok()?)
+}
+
+// KI2 relative words as JKF letters: 左 L, 直 C, 右 R, 上 U, 寄 M, 引 D, 打 H
+fn relative_letters(words: &str) -> String {
+    words.chars().filter_map(|c| match c {
+        '左' => Some('L'),
+        '直' => Some('C'),
+        '右' => Some('R'),
+        '上' => Some('U'),
+        '寄' => Some('M'),
+        '引' => Some('D'),
+        '打' => Some('H'),
+        _ => None,
+    }).collect()
+}
+
+fn time_json(secs: u32, total: u32) -> Json {
+    object(vec![
+        ("now", object(vec![("m", Json::Number((secs / 60) as f64)), ("s", Json::Number((secs % 60) as f64))])),
+        ("total", object(vec![
+            ("h", Json::Number((total / 3600) as f64)),
+            ("m", Json::Number((total / 60 % 60) as f64)),
+            ("s", Json::Number((total % 60) as f64)),
+        ])),
+    ])
+}
+
+fn comments_json<'a>(comments: impl Iterator<Item = &'a str>) -> Json {
+    Json::Array(comments.map(|c| Json::String(c.to_string())).collect())
+}
+
+// Initial position as a preset or a full board with hands
+fn initial_json(pos: &Position, start_sfen: &str) -> Json {
+    if let Some(i) = HANDICAPS.iter().position(|(_, sfen)| *sfen == start_sfen) {
+        return object(vec![("preset", Json::String(PRESETS[i].to_string()))]);
+    }
+
+    let board = (0..9).map(|file| {
+        Json::Array((0..9).map(|rank| match *pos.piece_at(Square::new(file, rank).unwrap()) {
+            Some(piece) => object(vec![
+                ("color", color_number(piece.color)),
+                ("kind", Json::String(csa::piece_code(piece.piece_type).to_string())),
+            ]),
+            None => object(Vec::new()),
+        }).collect())
+    }).collect();
+    let hands = [Color::Black, Color::White].iter().map(|&color| {
+        Json::Object(HAND_KINDS.iter().map(|&piece_type| {
+            (csa::piece_code(piece_type).to_string(), Json::Number(pos.hand(Piece { piece_type, color }) as f64))
+        }).collect())
+    }).collect();
+
+    object(vec![
+        ("preset", Json::String(String::from("OTHER"))),
+        ("data", object(vec![
+            ("color", color_number(pos.side_to_move())),
+            ("board", Json::Array(board)),
+            ("hands", Json::Array(hands)),
+        ])),
+    ])
+}
+
+fn parse_initial(json: &Json) -> Result<String, String> {
+    let preset = json.get("preset").and_then(Json::as_str).ok_or("initial needs a preset")?;
+    if let Some(i) = PRESETS.iter().position(|p| *p == preset) {
+        return Ok(HANDICAPS[i].1.to_string());
+    }
+    if preset != "OTHER" {
+        return Err(format!("Unknown preset {}", preset));
+    }
+
+    let data = json.get("data").ok_or("OTHER preset needs data")?;
+    let board = data.get("board").and_then(Json::as_array).filter(|b| b.len() == 9).ok_or("board must have 9 files")?;
+    let mut rows = Vec::new();
+    for rank in 0..9 {
+        let mut row = String::new();
+        let mut empty = 0;
+        for file in (0..9).rev() {
+            let cell = board[file].as_array().and_then(|f| f.get(rank)).ok_or("board files must have 9 ranks")?;
+            match cell.get("kind").and_then(Json::as_str) {
+                Some(kind) => {
+                    let piece_type = csa::parse_piece(kind).ok_or(format!("Unknown piece {}", kind))?;
+                    let color = if cell.get("color").and_then(Json::as_i64) == Some(1) { Color::White } else { Color::Black };
+                    if empty > 0 {
+                        row.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    row.push_str(&Piece { piece_type, color }.to_string());
+                }
+                None => empty += 1,
+            }
+        }
+        if empty > 0 {
+            row.push_str(&empty.to_string());
+        }
+        rows.push(row);
+    }
+
+    let mut hand = String::new();
+    let hands = data.get("hands").and_then(Json::as_array).unwrap_or_default();
+    for (color, pieces) in [Color::Black, Color::White].into_iter().zip(hands) {
+        // SFEN lists hands from the rook down
+        for &piece_type in HAND_KINDS.iter().rev() {
+            let count = pieces.get(csa::piece_code(piece_type)).and_then(Json::as_i64).unwrap_or(0);
+            if count > 1 {
+                hand.push_str(&count.to_string());
+            }
+            if count > 0 {
+                hand.push_str(&Piece { piece_type, color }.to_string());
+            }
+        }
+    }
+
+    let side = if data.get("color").and_then(Json::as_i64) == Some(1) { "w" } else { "b" };
+    Ok(format!("{} {} {} 1", rows.join("/"), side, if hand.is_empty() { "-" } else { &hand }))
+}
+
+// The move field of a move entry. "from" may be left out when "relative" or the position makes the piece clear.
+fn parse_move(pos: &Position, json: &Json) -> Option<Move> {
+    let to = parse_place(json.get("to")?)?;
+    let piece_type = csa::parse_piece(json.get("piece")?.as_str()?)?;
+    let promote = json.get("promote").and_then(Json::as_bool).unwrap_or(false);
+    if let Some(from) = json.get("from") {
+        return Some(Move::Normal { from: parse_place(from)?, to, promote });
+    }
+
+    let relative = json.get("relative").and_then(Json::as_str).unwrap_or_default();
+    let piece = Piece { piece_type, color: pos.side_to_move() };
+    let candidates = rules::pieces_reaching(pos, piece, to);
+    if relative.contains('H') || candidates.is_empty() {
+        return Some(Move::Drop { to, piece_type });
+    }
+    candidates.into_iter()
+        .find(|from| relative_letters(&notation::ki2_relative(pos, *from, to)) == relative)
+        .map(|from| Move::Normal { from, to, promote })
+}
+
+// Move entries played from the cursor, the "forks" of an entry are played from the position before its move
+fn parse_moves(record: &mut GameRecord, entries: &[Json]) -> Result<(), String> {
+    for entry in entries {
+        let comments: Vec<String> = entry.get("comments").and_then(Json::as_array).unwrap_or_default()
+            .iter().filter_map(|c| c.as_str().map(String::from)).collect();
+        let n = record.cursor + 1;
+        let mut pos = record.position().map_err(|err| format!("move {}: {}", n, err))?;
+
+        if let Some(special) = entry.get("special").and_then(Json::as_str) {
+            let end = csa::parse_end(&format!("%{}", special), pos.side_to_move()).ok_or(format!("move {}: unknown special {}", n, special))?;
+            record.end_at_cursor(end);
+            break;
+        }
+        let Some(mv) = entry.get("move") else {
+            // The first entry only holds comments on the initial position
+            if record.cursor == 0 {
+                record.start_comments.extend(comments);
+            }
+            continue;
+        };
+
+        let m = parse_move(&pos, mv).ok_or(format!("move {}: invalid move", n))?;
+        rules::make_move(&mut pos, m).map_err(|err| format!("move {}: illegal move {}: {}", n, m, err))?;
+        let now = entry.get("time").and_then(|t| t.get("now"));
+        let secs = now.map(|now| {
+            now.get("m").and_then(Json::as_i64).unwrap_or(0) * 60 + now.get("s").and_then(Json::as_i64).unwrap_or(0)
+        });
+        record.play(m, secs.map(|s| s as u32));
+        for comment in comments {
+            record.add_comment(comment);
+        }
+
+        for fork in entry.get("forks").and_then(Json::as_array).unwrap_or_default() {
+            let (path, cursor) = (record.path.clone(), record.cursor);
+            record.undo();
+            parse_moves(record, fork.as_array().ok_or(format!("move {}: forks must be arrays of moves", n))?)?;
+            (record.path, record.cursor) = (path, cursor);
+        }
+    }
+    Ok(())
+}
+
+pub fn parse(text: &str) -> Result<GameRecord, String> {
+    let json = Json::parse(text)?;
+
+    let start_sfen = match json.get("initial") {
+        Some(initial) => parse_initial(initial)?,
+        None => STARTPOS_SFEN.to_string(),
+    };
+    rules::position_from_sfen(&start_sfen).map_err(|err| format!("Invalid initial position: {}", err))?;
+    let mut record = GameRecord::new(&start_sfen);
+
+    for (key, value) in json.get("header").and_then(Json::as_object).unwrap_or_default() {
+        record.header.push((key.clone(), value.as_str().unwrap_or_default().to_string()));
+    }
+    parse_moves(&mut record, json.get("moves").and_then(Json::as_array).unwrap_or_default())?;
+    Ok(record)
+}
+
+// Entries for the moves of a line after the moves before it, with the variations of each move as its forks
+fn write_moves(start_sfen: &str, before: &[&RecordMove], moves: &[RecordMove], end: Option<GameEnd>) -> Vec<Json> {
+    let usi: Vec<String> = before.iter().map(|rm| rm.mv.to_string()).collect();
+    let sfen = if usi.is_empty() { start_sfen.to_string() } else { format!("{} moves {}", start_sfen, usi.join(" ")) };
+    let Ok(mut pos) = rules::position_from_sfen(&sfen) else {
+        return Vec::new();
+    };
+
+    let mut entries = Vec::new();
+    let mut last_to = before.last().map(|rm| rules::destination(rm.mv));
+    // total is the player's own, sides alternate from the start position
+    let mut totals = [0u32; 2];
+    let first = rules::sfen_side_to_move(start_sfen);
+    for (i, rm) in before.iter().enumerate() {
+        let mover = if i % 2 == 0 { first } else { first.flip() };
+        totals[mover.index()] += rm.time_secs.unwrap_or(0);
+    }
+    for (i, rm) in moves.iter().enumerate() {
+        let color = pos.side_to_move();
+        let to = rules::destination(rm.mv);
+        let mut fields = vec![("color", color_number(color))];
+        match rm.mv {
+            Move::Normal { from, promote, .. } => {
+                let piece = pos.piece_at(from).unwrap_or(Piece { piece_type: PieceType::King, color });
+                fields.push(("from", place(from)));
+                fields.push(("to", place(to)));
+                fields.push(("piece", Json::String(csa::piece_code(piece.piece_type).to_string())));
+                if rules::can_promote(piece, from, to) {
+                    fields.push(("promote", Json::Bool(promote)));
+                }
+                let relative = relative_letters(&notation::ki2_relative(&pos, from, to));
+                if !relative.is_empty() {
+                    fields.push(("relative", Json::String(relative)));
+                }
+            }
+            Move::Drop { piece_type, .. } => {
+                fields.push(("to", place(to)));
+                fields.push(("piece", Json::String(csa::piece_code(piece_type).to_string())));
+                if !rules::pieces_reaching(&pos, Piece { piece_type, color }, to).is_empty() {
+                    fields.push(("relative", Json::String(String::from("H"))));
+                }
+            }
+        }
+        if last_to == Some(to) {
+            fields.push(("same", Json::Bool(true)));
+        }
+        if let Some(captured) = *pos.piece_at(to) {
+            fields.push(("capture", Json::String(csa::piece_code(captured.piece_type).to_string())));
+        }
+
+        let mut entry = vec![("move", object(fields))];
+        if let Some(secs) = rm.time_secs {
+            totals[color.index()] += secs;
+            entry.push(("time", time_json(secs, totals[color.index()])));
+        }
+        if rm.comment_lines().next().is_some() {
+            entry.push(("comments", comments_json(rm.comment_lines())));
+        }
+        if !rm.variations.is_empty() {
+            let line: Vec<&RecordMove> = before.iter().copied().chain(&moves[..i]).collect();
+            let forks = rm.variations.iter()
+                .map(|variation| Json::Array(write_moves(start_sfen, &line, &variation.moves, variation.end)))
+                .collect();
+            entry.push(("forks", Json::Array(forks)));
+        }
+        entries.push(object(entry));
+
+        if rules::make_move(&mut pos, rm.mv).is_err() {
+            break;
+        }
+        last_to = Some(to);
+    }
+
+    if let Some(end) = end {
+        let special = csa::end_code(end, pos.side_to_move());
+        entries.push(object(vec![("special", Json::String(special.trim_start_matches('%').to_string()))]));
+    }
+    entries
+}
+
+pub fn write(record: &GameRecord) -> String {
+    let header = Json::Object(record.header.iter().map(|(k, v)| (k.clone(), Json::String(v.clone()))).collect());
+    let Ok(pos) = rules::position_from_sfen(&record.start_sfen) else {
+        return object(vec![("header", header)]).to_string_pretty();
+    };
+
+    let mut moves = vec![if record.start_comments.is_empty() {
+        object(Vec::new())
+    }
+    else {
+        object(vec![("comments", comments_json(record.start_comments.iter().map(String::as_str)))])
+    }];
+    moves.extend(write_moves(&record.start_sfen, &[], &record.moves, record.end));
+
+    object(vec![
+        ("header", header),
+        ("initial", initial_json(&pos, &record.start_sfen)),
+        ("moves", Json::Array(moves)),
+    ]).to_string_pretty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bishop handicap game with times, comments and a fork at the fourth move
+    const JKF: &str = r#"{
+  "header": {"開始日時": "2024/05/01 10:00:00", "上手": "上手太郎", "下手": "下手花子"},
+  "initial": {"preset": "KA"},
+  "moves": [
+    {"comments": ["対局開始"]},
+    {"move": {"color": 1, "from": {"x": 3, "y": 3}, "to": {"x": 3, "y": 4}, "piece": "FU"},
+     "time": {"now": {"m": 0, "s": 3}, "total": {"h": 0, "m": 0, "s": 3}}},
+    {"move": {"color": 0, "from": {"x": 7, "y": 7}, "to": {"x": 7, "y": 6}, "piece": "FU"},
+     "time": {"now": {"m": 0, "s": 5}, "total": {"h": 0, "m": 0, "s": 5}}},
+    {"move": {"color": 1, "from": {"x": 7, "y": 1}, "to": {"x": 6, "y": 2}, "piece": "GI"},
+     "time": {"now": {"m": 0, "s": 2}, "total": {"h": 0, "m": 0, "s": 5}}},
+    {"move": {"color": 0, "from": {"x": 8, "y": 8}, "to": {"x": 2, "y": 2}, "piece": "KA", "promote": true},
+     "time": {"now": {"m": 0, "s": 10}, "total": {"h": 0, "m": 0, "s": 15}},
+     "comments": ["角を切る"],
+     "forks": [[
+       {"move": {"color": 0, "from": {"x": 8, "y": 8}, "to": {"x": 2, "y": 2}, "piece": "KA", "promote": false},
+        "time": {"now": {"m": 0, "s": 20}, "total": {"h": 0, "m": 0, "s": 25}}},
+       {"move": {"color": 1, "from": {"x": 3, "y": 1}, "to": {"x": 2, "y": 2}, "piece": "GI", "same": true, "capture": "KA"}},
+       {"special": "TORYO"}
+     ]]},
+    {"move": {"color": 1, "from": {"x": 3, "y": 1}, "to": {"x": 2, "y": 2}, "piece": "GI", "same": true, "capture": "UM"},
+     "time": {"now": {"m": 1, "s": 2}, "total": {"h": 0, "m": 1, "s": 7}}},
+    {"special": "TORYO"}
+  ]
+}"#;
+
+    fn usi(moves: &[RecordMove]) -> Vec<String> {
+        moves.iter().map(|rm| rm.mv.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_preset_and_fork() {
+        shogi::bitboard::Factory::init();
+        let record = parse(JKF).unwrap();
+
+        assert_eq!(record.header_value("上手"), Some("上手太郎"));
+        assert_eq!(record.start_sfen, HANDICAPS.iter().find(|(name, _)| *name == "角落ち").unwrap().1);
+        assert_eq!(record.start_comments, vec![String::from("対局開始")]);
+        assert_eq!(usi(&record.moves), ["3c3d", "7g7f", "7a6b", "8h2b+", "3a2b"]);
+        assert_eq!(record.moves[3].comments, vec![String::from("角を切る")]);
+        assert_eq!(record.moves[4].time_secs, Some(62));
+        assert_eq!(record.end, Some(GameEnd::Resign));
+
+        let variation = &record.moves[3].variations[0];
+        assert_eq!(usi(&variation.moves), ["8h2b", "3a2b"]);
+        assert_eq!(variation.moves[0].time_secs, Some(20));
+        assert_eq!(variation.end, Some(GameEnd::Resign));
+        assert!(record.path.is_empty());
+    }
+
+    #[test]
+    fn writes_back_unchanged() {
+        shogi::bitboard::Factory::init();
+        let text = write(&parse(JKF).unwrap());
+        assert_eq!(Json::parse(&text).unwrap(), Json::parse(JKF).unwrap());
+        assert_eq!(write(&parse(&text).unwrap()), text);
+    }
+}
@@ -0,0 +1,576 @@
+// KIF game records, the format used by Kifu for Windows, ShogiGUI and most Japanese shogi software
+// http://kakinoki.o.oo7.jp/kif_format.html
+// Files are read and written as UTF-8 (".kifu"), Shift_JIS ".kif" files have to be converted first.
+
+use shogi::{Color, Move, Piece, PieceType, Position, Square};
+
+use crate::notation::{self, KANJI_NUMERALS};
+use crate::record::{GameEnd, GameRecord, RecordMove, STARTPOS_SFEN};
+use crate::rules;
+
+// 手合割 names and their start positions, the handicap giver (上手) is white and moves first
+pub const HANDICAPS: [(&str, &str); 11] = [
+    ("平手",     STARTPOS_SFEN),
+    ("香落ち",   "lnsgkgsn1/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("右香落ち", "1nsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("角落ち",   "lnsgkgsnl/1r7/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("飛車落ち", "lnsgkgsnl/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("飛香落ち", "lnsgkgsn1/7b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("二枚落ち", "lnsgkgsnl/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("四枚落ち", "1nsgkgsn1/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("六枚落ち", "2sgkgs2/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("八枚落ち", "3gkg3/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+    ("十枚落ち", "4k4/9/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL w - 1"),
+];
+
+// Piece names in move lines, longest first so 成香 is not read as 成 + 香
+const MOVE_PIECES: [(&str, PieceType); 19] = [
+    ("成香", PieceType::ProLance), ("成桂", PieceType::ProKnight), ("成銀", PieceType::ProSilver),
+    ("歩", PieceType::Pawn), ("香", PieceType::Lance), ("桂", PieceType::Knight), ("銀", PieceType::Silver),
+    ("金", PieceType::Gold), ("角", PieceType::Bishop), ("飛", PieceType::Rook), ("玉", PieceType::King),
+    ("王", PieceType::King), ("と", PieceType::ProPawn), ("杏", PieceType::ProLance), ("圭", PieceType::ProKnight),
+    ("全", PieceType::ProSilver), ("馬", PieceType::ProBishop), ("龍", PieceType::ProRook), ("竜", PieceType::ProRook),
+];
+
+// One character piece names used in board diagrams
+fn bod_piece(piece_type: PieceType) -> char {
+    match piece_type {
+        PieceType::Pawn      => '歩',
+        PieceType::Lance     => '香',
+        PieceType::Knight    => '桂',
+        PieceType::Silver    => '銀',
+        PieceType::Gold      => '金',
+        PieceType::Bishop    => '角',
+        PieceType::Rook      => '飛',
+        PieceType::King      => '玉',
+        PieceType::ProPawn   => 'と',
+        PieceType::ProLance  => '杏',
+        PieceType::ProKnight => '圭',
+        PieceType::ProSilver => '全',
+        PieceType::ProBishop => '馬',
+        PieceType::ProRook   => '龍',
+    }
+}
+
+fn piece_from_bod(c: char) -> Option<PieceType> {
+    MOVE_PIECES.iter().find(|(name, _)| name.chars().count() == 1 && name.starts_with(c)).map(|(_, pt)| *pt)
+}
+
+// Hand order in board diagrams and SFEN
+const HAND_PIECES: [PieceType; 7] = [
+    PieceType::Rook, PieceType::Bishop, PieceType::Gold, PieceType::Silver, PieceType::Knight, PieceType::Lance, PieceType::Pawn,
+];
+
+// 一 to 十八, the largest count a hand can hold
+fn parse_kanji_number(s: &str) -> Option<u32> {
+    if s.is_empty() {
+        return Some(1);
+    }
+    let digit = |c: char| KANJI_NUMERALS.iter().position(|k| k.starts_with(c)).map(|n| n as u32);
+    match s.split_once('十') {
+        Some((tens, ones)) => {
+            let tens = if tens.is_empty() { 1 } else { digit(tens.chars().next()?)? };
+            let ones = if ones.is_empty() { 0 } else { digit(ones.chars().next()?)? };
+            Some(tens * 10 + ones)
+        }
+        None => digit(s.chars().next()?),
+    }
+}
+
+fn kanji_number(n: u32) -> String {
+    match n {
+        0..=1 => String::new(),
+        2..=9 => KANJI_NUMERALS[n as usize].to_string(),
+        10 => String::from("十"),
+        _ => format!("十{}", KANJI_NUMERALS[n as usize % 10]),
+    }
+}
+
+// "７六歩(77)", "同　歩(23)", "２二角成(88)", "３三銀不成(44)" or "５五角打"
+fn parse_move(text: &str, last_to: Option<Square>) -> Option<Move> {
+    let mut rest = text;
+    let to = if let Some(after) = rest.strip_prefix('同') {
+        rest = after.trim_start_matches(['　', ' ']);
+        last_to?
+    }
+    else {
+        let mut chars = rest.chars();
+        let sq = notation::parse_kanji_square(chars.next()?, chars.next()?)?;
+        rest = chars.as_str();
+        sq
+    };
+
+    let (name, piece_type) = MOVE_PIECES.iter().find(|(name, _)| rest.starts_with(name))?;
+    rest = &rest[name.len()..];
+
+    if rest.starts_with('打') {
+        return Some(Move::Drop { to, piece_type: *piece_type });
+    }
+    let promote = if let Some(after) = rest.strip_prefix("不成") {
+        rest = after;
+        false
+    }
+    else if let Some(after) = rest.strip_prefix('成') {
+        rest = after;
+        true
+    }
+    else {
+        false
+    };
+
+    // Drops of an unambiguous piece may omit 打
+    let Some(origin) = rest.strip_prefix('(') else {
+        return Some(Move::Drop { to, piece_type: *piece_type });
+    };
+    let mut digits = origin.chars();
+    let from = Square::new(digits.next()?.to_digit(10)?.checked_sub(1)? as u8, digits.next()?.to_digit(10)?.checked_sub(1)? as u8)?;
+    Some(Move::Normal { from, to, promote })
+}
+
+// "( 0:01/00:00:01)", only the time for the move is kept
+fn parse_time(text: &str) -> Option<u32> {
+    let inner = text.trim().strip_prefix('(')?.split('/').next()?.trim();
+    let (min, sec) = inner.split_once(':')?;
+    Some(min.trim().parse::<u32>().ok()? * 60 + sec.trim().parse::<u32>().ok()?)
+}
+
+// Board diagram: hands, side to move and the "|v香v桂...|一" rows
+#[derive(Default)]
+struct Diagram {
+    rows: Vec<String>,
+    hands: [String; 2], // SFEN hand for black and white
+    white_to_move: bool,
+}
+
+impl Diagram {
+    fn parse_row(&mut self, line: &str) -> Option<()> {
+        let cells: Vec<char> = line.strip_prefix('|')?.chars().take_while(|c| *c != '|').collect();
+        let mut row = String::new();
+        let mut empty = 0;
+        for cell in cells.chunks(2) {
+            let [side, name] = cell else {
+                return None;
+            };
+            let Some(piece_type) = piece_from_bod(*name) else {
+                empty += 1;
+                continue;
+            };
+            if empty > 0 {
+                row.push_str(&empty.to_string());
+                empty = 0;
+            }
+            let color = if *side == 'v' { Color::White } else { Color::Black };
+            row.push_str(&Piece { piece_type, color }.to_string());
+        }
+        if empty > 0 {
+            row.push_str(&empty.to_string());
+        }
+        self.rows.push(row);
+        Some(())
+    }
+
+    // "飛　角二　歩十八" or "なし"
+    fn parse_hand(&mut self, color: Color, text: &str) -> Option<()> {
+        let mut sfen = String::new();
+        for token in text.split_whitespace().filter(|token| *token != "なし") {
+            let mut chars = token.chars();
+            let piece_type = piece_from_bod(chars.next()?)?;
+            let count = parse_kanji_number(chars.as_str())?;
+            if count > 1 {
+                sfen.push_str(&count.to_string());
+            }
+            sfen.push_str(&Piece { piece_type, color }.to_string());
+        }
+        self.hands[color.index()] = sfen;
+        Some(())
+    }
+
+    fn to_sfen(&self) -> Option<String> {
+        if self.rows.len() != 9 {
+            return None;
+        }
+        let hand = format!("{}{}", self.hands[0], self.hands[1]);
+        Some(format!(
+            "{} {} {} 1",
+            self.rows.join("/"),
+            if self.white_to_move { "w" } else { "b" },
+            if hand.is_empty() { "-" } else { &hand },
+        ))
+    }
+}
+
+// Lines after the header, played into the record once the start position is known
+enum Entry {
+    Move(usize, String, Option<u32>), // Line number, move text, time
+    Comment(String),
+    End(GameEnd),
+    Variation(usize, usize), // Line number, move number it replaces
+}
+
+pub fn parse(text: &str) -> Result<GameRecord, String> {
+    let mut header = Vec::new();
+    let mut start_sfen = String::from(STARTPOS_SFEN);
+    let mut diagram = Diagram::default();
+    let mut entries = Vec::new(); // After the header
+    let mut ended = false; // Lines after the end of a line are skipped until the next variation
+
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim_start_matches('\u{feff}').trim_end();
+        let trimmed = line.trim_start();
+
+        if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('&') {
+            continue;
+        }
+        if let Some(comment) = trimmed.strip_prefix('*') {
+            entries.push(Entry::Comment(comment.to_string()));
+            continue;
+        }
+        if let Some(ply) = trimmed.strip_prefix("変化：") {
+            let ply = ply.trim_end_matches('手').trim().parse().ok().filter(|&ply| ply > 0);
+            entries.push(Entry::Variation(i + 1, ply.ok_or(format!("line {}: invalid variation {}", i + 1, trimmed))?));
+            ended = false;
+            continue;
+        }
+        if ended || trimmed.starts_with("まで") || trimmed.starts_with("手数") || trimmed.starts_with('+') || trimmed.starts_with('９') {
+            continue;
+        }
+        if trimmed.starts_with('|') {
+            diagram.parse_row(trimmed).ok_or(format!("line {}: invalid board row", i + 1))?;
+            continue;
+        }
+        if trimmed == "後手番" || trimmed == "上手番" {
+            diagram.white_to_move = true;
+            continue;
+        }
+        if trimmed == "先手番" || trimmed == "下手番" {
+            continue;
+        }
+
+        if trimmed.starts_with(|c: char| c.is_ascii_digit()) {
+            let (_, rest) = trimmed.split_once(char::is_whitespace).ok_or(format!("line {}: missing move", i + 1))?;
+            let rest = rest.trim_start();
+            // 同　歩 contains a full-width space, the move ends at the origin or 打
+            let split = [rest.find('打').map(|n| n + '打'.len_utf8()), rest.find(')').map(|n| n + 1)]
+                .into_iter()
+                .flatten()
+                .min()
+                .unwrap_or(rest.find(char::is_whitespace).unwrap_or(rest.len()));
+            let (move_text, time) = rest.split_at(split);
+
+            match GameEnd::from_kif(move_text.split_whitespace().next().unwrap_or_default()) {
+                Some(kind) => {
+                    entries.push(Entry::End(kind));
+                    ended = true;
+                }
+                None => entries.push(Entry::Move(i + 1, move_text.to_string(), parse_time(time))),
+            }
+            continue;
+        }
+
+        let Some((key, value)) = trimmed.split_once('：') else {
+            return Err(format!("line {}: unexpected {}", i + 1, trimmed));
+        };
+        let value = value.trim();
+        match key {
+            "手合割" => {
+                start_sfen = HANDICAPS.iter().find(|(name, _)| *name == value)
+                    .map(|(_, sfen)| sfen.to_string())
+                    .ok_or(format!("line {}: unknown handicap {}", i + 1, value))?;
+            }
+            "先手の持駒" | "下手の持駒" => {
+                diagram.parse_hand(Color::Black, value).ok_or(format!("line {}: invalid hand", i + 1))?;
+            }
+            "後手の持駒" | "上手の持駒" => {
+                diagram.parse_hand(Color::White, value).ok_or(format!("line {}: invalid hand", i + 1))?;
+            }
+            _ => header.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    if !diagram.rows.is_empty() {
+        start_sfen = diagram.to_sfen().ok_or("Board diagram must have 9 rows")?;
+    }
+
+    let mut record = GameRecord::new(&start_sfen);
+    record.header = header;
+    let mut pos = rules::position_from_sfen(&start_sfen).map_err(|err| format!("Invalid start position: {}", err))?;
+    for entry in entries {
+        match entry {
+            Entry::Move(line, text, time) => {
+                let last_to = record.last_move().map(rules::destination);
+                let m = parse_move(&text, last_to).ok_or(format!("line {}: invalid move {}", line, text))?;
+                rules::make_move(&mut pos, m).map_err(|err| format!("line {}: illegal move {}: {}", line, text, err))?;
+                record.play(m, time);
+            }
+            Entry::Comment(comment) => record.add_comment(comment),
+            Entry::End(end) => record.end_at_cursor(end),
+            Entry::Variation(line, ply) => {
+                // 変化：N手 replaces move N of the latest line that has one
+                record.path.retain(|&(branch, _)| branch < ply - 1);
+                if record.line().len() < ply {
+                    return Err(format!("line {}: no move {} to branch from", line, ply));
+                }
+                record.seek(ply - 1);
+                pos = record.position().map_err(|err| format!("line {}: {}", line, err))?;
+            }
+        }
+    }
+    // Back to the end of the main line
+    record.path.clear();
+    record.seek(record.moves.len());
+    Ok(record)
+}
+
+// Board diagram lines for positions that are not a standard handicap
+fn write_diagram(pos: &Position, out: &mut String) {
+    let hand = |color: Color| {
+        let pieces: Vec<String> = HAND_PIECES.iter().filter_map(|&piece_type| {
+            let count = pos.hand(Piece { piece_type, color });
+            (count > 0).then(|| format!("{}{}", bod_piece(piece_type), kanji_number(count as u32)))
+        }).collect();
+        if pieces.is_empty() { String::from("なし") } else { pieces.join("　") }
+    };
+
+    out.push_str(&format!("後手の持駒：{}\n", hand(Color::White)));
+    out.push_str("  ９ ８ ７ ６ ５ ４ ３ ２ １\n");
+    out.push_str("+---------------------------+\n");
+    for rank in 0..9 {
+        out.push('|');
+        for file in (0..9).rev() {
+            match *pos.piece_at(Square::new(file, rank).unwrap()) {
+                Some(piece) => {
+                    out.push(if piece.color == Color::White { 'v' } else { ' ' });
+                    out.push(bod_piece(piece.piece_type));
+                }
+                None => out.push_str(" ・"),
+            }
+        }
+        out.push_str(&format!("|{}\n", KANJI_NUMERALS[rank as usize + 1]));
+    }
+    out.push_str("+---------------------------+\n");
+    out.push_str(&format!("先手の持駒：{}\n", hand(Color::Black)));
+    if pos.side_to_move() == Color::White {
+        out.push_str("後手番\n");
+    }
+}
+
+// Full-width characters take two columns when aligning the time column
+fn display_width(text: &str) -> usize {
+    text.chars().map(|c| if c.is_ascii() { 1 } else { 2 }).sum()
+}
+
+// Moves of a line after the moves before it, then its variations from the last one back
+// so every 変化 follows the line it branches off
+fn write_line(out: &mut String, start_sfen: &str, before: &[&RecordMove], moves: &[RecordMove], end: Option<GameEnd>) {
+    let usi: Vec<String> = before.iter().map(|rm| rm.mv.to_string()).collect();
+    let sfen = if usi.is_empty() { start_sfen.to_string() } else { format!("{} moves {}", start_sfen, usi.join(" ")) };
+    let Ok(mut pos) = rules::position_from_sfen(&sfen) else {
+        return;
+    };
+
+    // The second time is the player's total, sides alternate from the start position
+    let mut totals = [0u32; 2];
+    let first = rules::sfen_side_to_move(start_sfen);
+    for (i, rm) in before.iter().enumerate() {
+        let mover = if i % 2 == 0 { first } else { first.flip() };
+        totals[mover.index()] += rm.time_secs.unwrap_or(0);
+    }
+
+    let mut last_to = before.last().map(|rm| rules::destination(rm.mv));
+    for (i, rm) in moves.iter().enumerate() {
+        let text = notation::kif_move(&pos, rm.mv, last_to);
+        out.push_str(&format!("{:>4} {}", before.len() + i + 1, text));
+        if let Some(secs) = rm.time_secs {
+            let mover = pos.side_to_move().index();
+            totals[mover] += secs;
+            let total = totals[mover];
+            out.push_str(&" ".repeat(14usize.saturating_sub(display_width(&text)).max(1)));
+            out.push_str(&format!("({:>2}:{:02}/{:02}:{:02}:{:02})", secs / 60, secs % 60, total / 3600, total / 60 % 60, total % 60));
+        }
+        // Moves with variations are marked with +
+        if !rm.variations.is_empty() {
+            out.push('+');
+        }
+        out.push('\n');
+        for comment in rm.comment_lines() {
+            out.push_str(&format!("*{}\n", comment));
+        }
+
+        if rules::make_move(&mut pos, rm.mv).is_err() {
+            break;
+        }
+        last_to = Some(rules::destination(rm.mv));
+    }
+
+    if let Some(end) = end {
+        let n = before.len() + moves.len();
+        out.push_str(&format!("{:>4} {}\n", n + 1, end.kif_name()));
+        // Handicap games call the sides 下手 and 上手
+        let handicap = HANDICAPS[1..].iter().any(|(_, sfen)| *sfen == start_sfen);
+        let side = |color: Color| match (color, handicap) {
+            (Color::Black, false) => "先手",
+            (Color::White, false) => "後手",
+            (Color::Black, true)  => "下手",
+            (Color::White, true)  => "上手",
+        };
+        match end.side_to_move_wins() {
+            Some(true)  => out.push_str(&format!("まで{}手で{}の勝ち\n", n, side(pos.side_to_move()))),
+            Some(false) => out.push_str(&format!("まで{}手で{}の勝ち\n", n, side(pos.side_to_move().flip()))),
+            None        => out.push_str(&format!("まで{}手で{}\n", n, end.kif_name())),
+        }
+    }
+
+    for (i, rm) in moves.iter().enumerate().rev() {
+        for variation in &rm.variations {
+            out.push_str(&format!("\n変化：{}手\n", before.len() + i + 1));
+            let line: Vec<&RecordMove> = before.iter().copied().chain(&moves[..i]).collect();
+            write_line(out, start_sfen, &line, &variation.moves, variation.end);
+        }
+    }
+}
+
+pub fn write(record: &GameRecord) -> String {
+    let mut out = String::from("#KIF version=2.0 encoding=UTF-8\n");
+    for (key, value) in &record.header {
+        out.push_str(&format!("{}：{}\n", key, value));
+    }
+
+    let Ok(pos) = rules::position_from_sfen(&record.start_sfen) else {
+        return out;
+    };
+    match HANDICAPS.iter().find(|(_, sfen)| *sfen == record.start_sfen) {
+        Some((name, _)) => out.push_str(&format!("手合割：{}\n", name)),
+        None => write_diagram(&pos, &mut out),
+    }
+
+    out.push_str("手数----指手---------消費時間--\n");
+    for comment in &record.start_comments {
+        out.push_str(&format!("*{}\n", comment));
+    }
+    write_line(&mut out, &record.start_sfen, &[], &record.moves, record.end);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::record::Glyph;
+
+    // Kifu for Windows style bishop handicap game with times, comments and a variation
+    const KIFU: &str = "\
+# ---- Kifu for Windows V7 V7.70 棋譜ファイル ----
+開始日時：2024/05/01(水) 10:00:00
+棋戦：練習対局
+手合割：角落ち
+上手：上手太郎
+下手：下手花子
+手数----指手---------消費時間--
+*対局開始
+   1 ３四歩(33)   ( 0:03/00:00:03)
+   2 ７六歩(77)   ( 0:05/00:00:05)
+   3 ６二銀(71)   ( 0:02/00:00:05)
+   4 ２二角成(88)   ( 0:10/00:00:15)+
+*!?
+*角を切る
+   5 同　銀(31)   ( 0:01/00:00:06)
+   6 ７七桂(89)   ( 0:04/00:00:19)
+   7 ５五角打   ( 1:02/00:01:08)
+   8 投了   ( 0:01/00:00:20)
+まで7手で上手の勝ち
+
+変化：4手
+   4 ２二角不成(88)   ( 0:20/00:00:25)
+   5 同　銀(31)   ( 0:01/00:00:06)
+   6 投了
+まで5手で上手の勝ち
+";
+
+    fn usi(moves: &[RecordMove]) -> Vec<String> {
+        moves.iter().map(|rm| rm.mv.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_kifu_for_windows_record() {
+        shogi::bitboard::Factory::init();
+        let record = parse(KIFU).unwrap();
+
+        assert_eq!(record.header_value("開始日時"), Some("2024/05/01(水) 10:00:00"));
+        assert_eq!(record.header_value("上手"), Some("上手太郎"));
+        assert_eq!(record.header_value("下手"), Some("下手花子"));
+        assert_eq!(record.start_sfen, HANDICAPS.iter().find(|(name, _)| *name == "角落ち").unwrap().1);
+        assert_eq!(record.start_comments, vec![String::from("対局開始")]);
+
+        assert_eq!(usi(&record.moves), ["3c3d", "7g7f", "7a6b", "8h2b+", "3a2b", "8i7g", "B*5e"]);
+        let times: Vec<Option<u32>> = record.moves.iter().map(|rm| rm.time_secs).collect();
+        assert_eq!(times, [Some(3), Some(5), Some(2), Some(10), Some(1), Some(4), Some(62)]);
+        assert_eq!(record.moves[3].glyph, Some(Glyph::Interesting));
+        assert_eq!(record.moves[3].comments, vec![String::from("角を切る")]);
+        assert_eq!(record.end, Some(GameEnd::Resign));
+
+        // 変化：4手 hangs from the fourth move
+        let variation = &record.moves[3].variations[0];
+        assert_eq!(usi(&variation.moves), ["8h2b", "3a2b"]);
+        assert_eq!(variation.moves[0].time_secs, Some(20));
+        assert_eq!(variation.end, Some(GameEnd::Resign));
+        assert_eq!(record.cursor, 7);
+        assert!(record.path.is_empty());
+    }
+
+    #[test]
+    fn writes_moves_in_kif_notation() {
+        shogi::bitboard::Factory::init();
+        let text = write(&parse(KIFU).unwrap());
+
+        assert!(text.contains("手合割：角落ち\n"));
+        assert!(text.contains("   4 ２二角成(88)  ( 0:10/00:00:15)+\n*!?\n*角を切る\n"));
+        assert!(text.contains("   5 同　銀(31)    ( 0:01/00:00:06)\n"));
+        assert!(text.contains("   7 ５五角打      ( 1:02/00:01:08)\n"));
+        assert!(text.contains("   8 投了\nまで7手で上手の勝ち\n"));
+        assert!(text.contains("\n変化：4手\n   4 ２二角不成(88) ( 0:20/00:00:25)\n"));
+        assert!(text.contains("   6 投了\nまで5手で上手の勝ち\n"));
+    }
+
+    #[test]
+    fn round_trips_record() {
+        shogi::bitboard::Factory::init();
+        let record = parse(KIFU).unwrap();
+        let text = write(&record);
+        assert_eq!(parse(&text).unwrap(), record);
+        assert_eq!(write(&parse(&text).unwrap()), text);
+    }
+
+    #[test]
+    fn round_trips_board_diagram() {
+        shogi::bitboard::Factory::init();
+        let mut record = GameRecord::new("4k4/9/4P4/9/9/9/9/9/4K4 w GS2Pr2b 1");
+        record.play(rules::parse_move("5a4b").unwrap(), None);
+        record.play(rules::parse_move("G*5b").unwrap(), Some(7));
+        let text = write(&record);
+
+        assert!(text.contains("後手の持駒：飛　角二\n"));
+        assert!(text.contains("先手の持駒：金　銀　歩二\n"));
+        assert!(text.contains("後手番\n"));
+        assert_eq!(parse(&text).unwrap(), record);
+    }
+
+    #[test]
+    fn rejects_illegal_moves() {
+        shogi::bitboard::Factory::init();
+        assert!(parse("手合割：平手\n   1 ７五歩(77)\n").is_err());
+        assert!(parse("手合割：平手\n   1 ７六歩(77)\n変化：3手\n   3 ２六歩(27)\n").is_err());
+    }
+
+    #[test]
+    fn round_trips_result_branch() {
+        shogi::bitboard::Factory::init();
+        let mut record = GameRecord::from_sfen(&format!("{} moves 7g7f 3c3d 2g2f", STARTPOS_SFEN)).unwrap();
+        record.seek(2);
+        record.end_at_cursor(GameEnd::Resign);
+        let text = write(&record);
+        assert!(text.contains("   3 ２六歩(27)+\n\n変化：3手\n   3 投了\nまで2手で後手の勝ち\n"));
+
+        record.path.clear();
+        record.seek(3);
+        assert_eq!(parse(&text).unwrap(), record);
+    }
+}
@@ -1,8 +1,5 @@
 use shogi::Position;
-use std::process::{Command, Stdio};
-use std::sync::{mpsc, Arc};
-use std::thread;
-use std::io::{BufRead, BufReader};
+use std::sync::Arc;
 
 mod shogi_game;
 use shogi_game::ShogiGame;
@@ -12,47 +9,34 @@ mod piece_button;
 use piece_button::{PieceButton, PIECE_TYPES};
 mod joystick;
 use joystick::Joystick;
+mod engine;
+use engine::{Engine, EngineState};
+mod usi;
+use usi::UsiOptionType;
+mod config;
+use config::Config;
+mod rules;
+mod notation;
+mod record;
+use record::GameRecord;
+mod kif;
+mod csa;
+mod json;
+mod jkf;
 
 fn main() -> Result<(), eframe::Error> {
+    let config = Config::from_args(std::env::args().skip(1)).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(2);
+    });
+
     shogi::bitboard::Factory::init();
     let board = Board::new();
     let mut pos = Position::new();
     pos.set_sfen("lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1").unwrap();  
-    
-    // Run apery engine
-    let mut child = Command::new("./target/release/apery")
-        .current_dir("apery_rust")
-        .stdin(Stdio::piped())  
-        .stdout(Stdio::piped()) 
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("Failed to start Shogi engine");
-
-    let engine_input = child.stdin.take().expect("Failed to open stdin");
-    let engine_output = child.stdout.take().expect("Failed to open stdout");
-
-    let (engine_tx, engine_rx) = mpsc::channel::<String>();
-
-    thread::spawn(move || {
-        let reader = BufReader::new(engine_output);
-        for line in reader.lines() {
-            match line {
-                Ok(output) => {
-                    if let Err(err) = engine_tx.send(output) {
-                        eprintln!("Error sending engine output: {}", err);
-                        break;
-                    }
-                }
-                Err(err) => {
-                    eprintln!("Error reading engine output: {}", err);
-                    break;
-                }
-            }
-        }
-    });
 
     let options = eframe::NativeOptions {
-        viewport: eframe::egui::ViewportBuilder::default().with_inner_size([780.0, 740.0]).with_resizable(true).with_icon(Arc::new(load_icon())), 
+        viewport: eframe::egui::ViewportBuilder::default().with_inner_size([1060.0, 740.0]).with_resizable(true).with_icon(Arc::new(load_icon())), 
         ..Default::default()
     };
     eframe::run_native(
@@ -64,8 +48,7 @@ fn main() -> Result<(), eframe::Error> {
                 &cc.egui_ctx, 
                 pos, 
                 board,
-                engine_input,
-                engine_rx,
+                config,
             )))
         }),
     )
@@ -86,4 +69,4 @@ fn load_icon() -> egui::IconData {
 		width: icon_width,
 		height: icon_height,
 	}
-}
\ No newline at end of file
+}
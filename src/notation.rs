@@ -0,0 +1,414 @@
+use shogi::{Color, Move, Piece, PieceType, Position, Square};
+
+use crate::rules;
+
+pub const FULLWIDTH_DIGITS: [&str; 10] = ["０", "１", "２", "３", "４", "５", "６", "７", "８", "９"];
+pub const KANJI_NUMERALS: [&str; 10] = ["〇", "一", "二", "三", "四", "五", "六", "七", "八", "九"];
+
+pub fn side_mark(color: Color) -> &'static str {
+    match color {
+        Color::Black => "▲",
+        Color::White => "△",
+    }
+}
+
+pub fn kanji_piece(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::Pawn      => "歩",
+        PieceType::Lance     => "香",
+        PieceType::Knight    => "桂",
+        PieceType::Silver    => "銀",
+        PieceType::Gold      => "金",
+        PieceType::Bishop    => "角",
+        PieceType::Rook      => "飛",
+        PieceType::King      => "玉",
+        PieceType::ProPawn   => "と",
+        PieceType::ProLance  => "成香",
+        PieceType::ProKnight => "成桂",
+        PieceType::ProSilver => "成銀",
+        PieceType::ProBishop => "馬",
+        PieceType::ProRook   => "龍",
+    }
+}
+
+// Square as file digit and rank kanji, e.g. ７六
+pub fn kanji_square(sq: Square) -> String {
+    format!("{}{}", FULLWIDTH_DIGITS[sq.file() as usize + 1], KANJI_NUMERALS[sq.rank() as usize + 1])
+}
+
+// Inverse of kanji_square, ASCII file digits are accepted as well
+pub fn parse_kanji_square(file: char, rank: char) -> Option<Square> {
+    let file = FULLWIDTH_DIGITS.iter().position(|d| d.starts_with(file)).or(file.to_digit(10).map(|d| d as usize))?;
+    let rank = KANJI_NUMERALS.iter().position(|k| k.starts_with(rank))?;
+    Square::new(file.checked_sub(1)? as u8, rank.checked_sub(1)? as u8)
+}
+
+// KIF style move text such as ７六歩(77), 同　歩(77), ２二角成(88), ３三銀不成(44) or ５五角打.
+// last_to is the destination of the previous move, used for 同.
+pub fn kif_move(pos: &Position, m: Move, last_to: Option<Square>) -> String {
+    let dest = |to: Square| {
+        if last_to == Some(to) {
+            String::from("同　")
+        }
+        else {
+            kanji_square(to)
+        }
+    };
+
+    match m {
+        Move::Normal { from, to, promote } => {
+            let piece = pos.piece_at(from).unwrap_or(Piece { piece_type: PieceType::King, color: pos.side_to_move() });
+            let promotion = if promote {
+                "成"
+            }
+            else if rules::can_promote(piece, from, to) {
+                "不成"
+            }
+            else {
+                ""
+            };
+            format!("{}{}{}({}{})", dest(to), kanji_piece(piece.piece_type), promotion, from.file() + 1, from.rank() + 1)
+        }
+        Move::Drop { to, piece_type } => {
+            format!("{}{}打", dest(to), kanji_piece(piece_type))
+        }
+    }
+}
+
+// Relative position and movement words of KI2, e.g. 右上 or 直, empty when the move is unambiguous.
+// Movement (上/引/寄) is used first, then position (右/左/直), then both.
+pub fn ki2_relative(pos: &Position, from: Square, to: Square) -> String {
+    let Some(piece) = *pos.piece_at(from) else {
+        return String::new();
+    };
+    let candidates = rules::pieces_reaching(pos, piece, to);
+    if candidates.len() < 2 {
+        return String::new();
+    }
+
+    let color = piece.color;
+    // Forward for the mover, positive when moving up the board from its side
+    let advance = |from: Square| match color {
+        Color::Black => from.rank() as i32 - to.rank() as i32,
+        Color::White => to.rank() as i32 - from.rank() as i32,
+    };
+    let movement = |from: Square| match advance(from).signum() {
+        1  => "上",
+        -1 => "引",
+        _  => "寄",
+    };
+    // Rightness from the mover's point of view, black's right is the 1 file
+    let right = |sq: Square| match color {
+        Color::Black => -(sq.file() as i32),
+        Color::White => sq.file() as i32,
+    };
+
+    let same_movement: Vec<Square> = candidates.iter().copied().filter(|sq| movement(*sq) == movement(from)).collect();
+    if same_movement.len() == 1 {
+        return movement(from).to_string();
+    }
+
+    // 竜 and 馬 use 左/右 instead of 直
+    let straight = |sq: Square| sq.file() == to.file() && advance(sq) > 0;
+    let dragon_or_horse = matches!(piece.piece_type, PieceType::ProRook | PieceType::ProBishop);
+    let position = |group: &[Square]| -> Option<&str> {
+        if !dragon_or_horse && straight(from) && group.iter().filter(|sq| straight(**sq)).count() == 1 {
+            Some("直")
+        }
+        else if group.iter().all(|sq| *sq == from || right(*sq) < right(from)) {
+            Some("右")
+        }
+        else if group.iter().all(|sq| *sq == from || right(*sq) > right(from)) {
+            Some("左")
+        }
+        else {
+            None
+        }
+    };
+
+    match (position(&candidates), position(&same_movement)) {
+        (Some(word), _) => word.to_string(),
+        (None, Some(word)) => format!("{}{}", word, movement(from)),
+        (None, None) => movement(from).to_string(),
+    }
+}
+
+// KI2 style move text such as ７六歩, 同　歩, ５八金右, ２二角成, ３三銀不成 or ５五角打.
+// 打 is only written when a piece on the board could also move to the square.
+pub fn ki2_move(pos: &Position, m: Move, last_to: Option<Square>) -> String {
+    let to = rules::destination(m);
+    let dest = if last_to == Some(to) { String::from("同　") } else { kanji_square(to) };
+
+    match m {
+        Move::Normal { from, promote, .. } => {
+            let piece = pos.piece_at(from).unwrap_or(Piece { piece_type: PieceType::King, color: pos.side_to_move() });
+            let promotion = if promote {
+                "成"
+            }
+            else if rules::can_promote(piece, from, to) {
+                "不成"
+            }
+            else {
+                ""
+            };
+            format!("{}{}{}{}", dest, kanji_piece(piece.piece_type), ki2_relative(pos, from, to), promotion)
+        }
+        Move::Drop { piece_type, .. } => {
+            let piece = Piece { piece_type, color: pos.side_to_move() };
+            let drop = if rules::pieces_reaching(pos, piece, to).is_empty() { "" } else { "打" };
+            format!("{}{}{}", dest, kanji_piece(piece_type), drop)
+        }
+    }
+}
+
+// Reads a KI2 move such as ▲７六歩, 同　歩, ５八金右 or ２二角成 for the side to move.
+// The side mark is optional, the move is matched against every candidate so the text must be exact.
+pub fn parse_ki2(pos: &Position, text: &str, last_to: Option<Square>) -> Option<Move> {
+    let text = text.trim().trim_start_matches(['▲', '△', '☗', '☖']);
+    let (to, rest) = match text.strip_prefix('同') {
+        Some(rest) => (last_to?, rest.trim_start_matches(['　', ' '])),
+        None => {
+            let mut chars = text.chars();
+            (parse_kanji_square(chars.next()?, chars.next()?)?, chars.as_str())
+        }
+    };
+
+    // Longest piece name first so 成銀 is not read as 成 + 銀
+    let mut piece_types: Vec<PieceType> = PieceType::iter().collect();
+    piece_types.sort_by_key(|pt| std::cmp::Reverse(kanji_piece(*pt).len()));
+    let piece_type = piece_types.into_iter().find(|pt| rest.starts_with(kanji_piece(*pt)) || (*pt == PieceType::King && rest.starts_with('王')))?;
+    let name_len = if rest.starts_with('王') { '王'.len_utf8() } else { kanji_piece(piece_type).len() };
+    let suffix = &rest[name_len..];
+
+    let piece = Piece { piece_type, color: pos.side_to_move() };
+    let mut candidates: Vec<Move> = Vec::new();
+    for from in rules::pieces_reaching(pos, piece, to) {
+        candidates.push(Move::Normal { from, to, promote: false });
+        if rules::can_promote(piece, from, to) {
+            candidates.push(Move::Normal { from, to, promote: true });
+        }
+    }
+    if piece_type.is_hand_piece() && pos.hand(piece) > 0 && pos.piece_at(to).is_none() {
+        candidates.push(Move::Drop { to, piece_type });
+    }
+
+    let target = format!("{}{}", if last_to == Some(to) { String::from("同　") } else { kanji_square(to) }, kanji_piece(piece_type));
+    let wanted = format!("{}{}", target, suffix);
+    candidates.into_iter().find(|m| ki2_move(pos, *m, last_to) == wanted)
+}
+
+// Western (Hosking) square such as 7f, files are numbers and ranks letters from a at the top
+pub fn western_square(sq: Square) -> String {
+    format!("{}{}", sq.file() + 1, (b'a' + sq.rank()) as char)
+}
+
+pub fn western_piece(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::Pawn      => "P",
+        PieceType::Lance     => "L",
+        PieceType::Knight    => "N",
+        PieceType::Silver    => "S",
+        PieceType::Gold      => "G",
+        PieceType::Bishop    => "B",
+        PieceType::Rook      => "R",
+        PieceType::King      => "K",
+        PieceType::ProPawn   => "+P",
+        PieceType::ProLance  => "+L",
+        PieceType::ProKnight => "+N",
+        PieceType::ProSilver => "+S",
+        PieceType::ProBishop => "+B",
+        PieceType::ProRook   => "+R",
+    }
+}
+
+// Western move text such as P-7f, Bx2b+, S*5e, G6i-5h or S-4c=.
+// The origin is only written when another piece of the same kind can reach the square,
+// = marks a move that could have promoted but did not.
+pub fn western_move(pos: &Position, m: Move) -> String {
+    match m {
+        Move::Normal { from, to, promote } => {
+            let piece = pos.piece_at(from).unwrap_or(Piece { piece_type: PieceType::King, color: pos.side_to_move() });
+            let origin = if rules::pieces_reaching(pos, piece, to).len() > 1 { western_square(from) } else { String::new() };
+            let separator = if pos.piece_at(to).is_some() { "x" } else { "-" };
+            let promotion = if promote {
+                "+"
+            }
+            else if rules::can_promote(piece, from, to) {
+                "="
+            }
+            else {
+                ""
+            };
+            format!("{}{}{}{}{}", western_piece(piece.piece_type), origin, separator, western_square(to), promotion)
+        }
+        Move::Drop { to, piece_type } => format!("{}*{}", western_piece(piece_type), western_square(to)),
+    }
+}
+
+// How moves are written in the move list, status line and engine PV
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NotationStyle {
+    Usi,      // 7g7f
+    Western,  // P-7f
+    Japanese, // ▲７六歩
+}
+
+impl NotationStyle {
+    pub const ALL: [NotationStyle; 3] = [NotationStyle::Japanese, NotationStyle::Western, NotationStyle::Usi];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            NotationStyle::Usi      => "USI",
+            NotationStyle::Western  => "Western",
+            NotationStyle::Japanese => "Japanese",
+        }
+    }
+
+    // m played from pos, last_to is the destination of the previous move for 同
+    pub fn format(self, pos: &Position, m: Move, last_to: Option<Square>) -> String {
+        match self {
+            NotationStyle::Usi      => m.to_string(),
+            NotationStyle::Western  => western_move(pos, m),
+            NotationStyle::Japanese => format!("{}{}", side_mark(pos.side_to_move()), ki2_move(pos, m, last_to)),
+        }
+    }
+
+    // Moves in USI notation rendered against the position they are played from.
+    // Moves that cannot be played on the board are shown as is and end the conversion.
+    pub fn format_moves(self, sfen: &str, moves: &[String]) -> Vec<String> {
+        let Ok(mut pos) = rules::position_from_sfen(sfen) else {
+            return moves.to_vec();
+        };
+
+        let mut out = Vec::new();
+        let mut last_to = None;
+        for (i, token) in moves.iter().enumerate() {
+            let Some(m) = rules::parse_move(token) else {
+                out.extend(moves[i..].iter().cloned());
+                break;
+            };
+            let text = self.format(&pos, m, last_to);
+            if rules::make_move(&mut pos, m).is_err() {
+                out.extend(moves[i..].iter().cloned());
+                break;
+            }
+            out.push(text);
+            last_to = Some(rules::destination(m));
+        }
+        out
+    }
+
+    // Principal variation against the searched position, e.g. ▲７六歩 △３四歩
+    pub fn pv_to_string(self, sfen: &str, pv: &[String]) -> String {
+        self.format_moves(sfen, pv).join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // KI2 text of the USI move, checked to read back as the same move
+    fn ki2(sfen: &str, usi: &str, last_to: Option<&str>) -> String {
+        shogi::bitboard::Factory::init();
+        let pos = rules::position_from_sfen(sfen).unwrap();
+        let m = rules::parse_move(usi).unwrap();
+        let last_to = last_to.map(|sq| Square::from_sfen(sq).unwrap());
+        let text = ki2_move(&pos, m, last_to);
+        assert_eq!(parse_ki2(&pos, &text, last_to), Some(m), "{} {}", usi, text);
+        text
+    }
+
+    #[test]
+    fn ki2_right_left_and_straight() {
+        // Golds on 6i and 4i, black's right is the 1 file
+        let golds = "k8/9/9/9/9/9/9/9/3G1G2K b - 1";
+        assert_eq!(ki2(golds, "6i5h", None), "５八金左");
+        assert_eq!(ki2(golds, "4i5h", None), "５八金右");
+
+        let golds = "k8/9/9/9/9/9/9/9/4GG2K b - 1";
+        assert_eq!(ki2(golds, "5i5h", None), "５八金直");
+        assert_eq!(ki2(golds, "4i5h", None), "５八金右");
+
+        // White's right is the 9 file
+        let golds = "k2g1g3/9/9/9/9/9/9/9/8K w - 1";
+        assert_eq!(ki2(golds, "6a5b", None), "５二金右");
+        assert_eq!(ki2(golds, "4a5b", None), "５二金左");
+
+        let golds = "k3gg3/9/9/9/9/9/9/9/8K w - 1";
+        assert_eq!(ki2(golds, "5a5b", None), "５二金直");
+        assert_eq!(ki2(golds, "4a5b", None), "５二金左");
+    }
+
+    #[test]
+    fn ki2_up_back_and_sideways() {
+        let golds = "k8/9/9/9/9/9/9/5G3/3G4K b - 1";
+        assert_eq!(ki2(golds, "6i5h", None), "５八金上");
+        assert_eq!(ki2(golds, "4h5h", None), "５八金寄");
+
+        let silvers = "k8/9/9/9/9/9/5S3/9/3S4K b - 1";
+        assert_eq!(ki2(silvers, "6i5h", None), "５八銀上");
+        assert_eq!(ki2(silvers, "4g5h", None), "５八銀引");
+
+        let silvers = "k2s5/9/5s3/9/9/9/9/9/8K w - 1";
+        assert_eq!(ki2(silvers, "6a5b", None), "５二銀上");
+        assert_eq!(ki2(silvers, "4c5b", None), "５二銀引");
+    }
+
+    #[test]
+    fn ki2_same_square() {
+        let capture = "k8/9/9/9/9/9/9/4p4/3G1G2K b - 1";
+        assert_eq!(ki2(capture, "6i5h", Some("5h")), "同　金左");
+        assert_eq!(ki2(capture, "4i5h", Some("5h")), "同　金右");
+        assert_eq!(ki2(capture, "4i5h", None), "５八金右");
+    }
+
+    #[test]
+    fn ki2_drop_only_marked_when_a_piece_could_move_there() {
+        let sfen = "k8/9/9/9/9/9/9/9/3G4K b G 1";
+        assert_eq!(ki2(sfen, "G*5h", None), "５八金打");
+        assert_eq!(ki2(sfen, "6i5h", None), "５八金");
+        assert_eq!(ki2(sfen, "G*5e", None), "５五金");
+    }
+
+    #[test]
+    fn ki2_promotion() {
+        let sfen = "k8/9/9/5S3/9/9/9/9/8K b - 1";
+        assert_eq!(ki2(sfen, "4d4c+", None), "４三銀成");
+        assert_eq!(ki2(sfen, "4d4c", None), "４三銀不成");
+        assert_eq!(ki2(sfen, "4d3e", None), "３五銀");
+    }
+
+    fn western(sfen: &str, usi: &str) -> String {
+        shogi::bitboard::Factory::init();
+        let pos = rules::position_from_sfen(sfen).unwrap();
+        western_move(&pos, rules::parse_move(usi).unwrap())
+    }
+
+    #[test]
+    fn western_moves_captures_and_drops() {
+        assert_eq!(western(crate::record::STARTPOS_SFEN, "7g7f"), "P-7f");
+        let open = "lnsgkgsnl/1r5b1/pppppp1pp/6p2/9/2P6/PP1PPPPPP/1B5R1/LNSGKGSNL b - 1";
+        assert_eq!(western(open, "8h2b+"), "Bx2b+");
+        assert_eq!(western(open, "8h2b"), "Bx2b=");
+        assert_eq!(western("k8/9/9/9/9/9/9/9/8K b S 1", "S*5e"), "S*5e");
+    }
+
+    #[test]
+    fn western_declined_promotion() {
+        let sfen = "k8/9/3R5/9/9/9/9/9/8K b - 1";
+        assert_eq!(western(sfen, "6c6h"), "R-6h=");
+        assert_eq!(western(sfen, "6c6h+"), "R-6h+");
+        assert_eq!(western(sfen, "6c6d"), "R-6d=");
+        assert_eq!(western("k8/9/9/9/3R5/9/9/9/8K b - 1", "6e6h"), "R-6h");
+    }
+
+    #[test]
+    fn western_origin_only_when_ambiguous() {
+        let golds = "k8/9/9/9/9/9/9/9/3G1G2K b - 1";
+        assert_eq!(western(golds, "6i5h"), "G6i-5h");
+        assert_eq!(western(golds, "4i5h"), "G4i-5h");
+        assert_eq!(western(golds, "6i6h"), "G-6h");
+        assert_eq!(western("k8/9/9/9/9/9/9/4p4/3G1G2K b - 1", "6i5h"), "G6ix5h");
+    }
+}
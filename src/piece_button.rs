@@ -97,7 +97,7 @@ impl<'a> PieceButton<'a> {
 
         PieceButton {
             button,
-            piece: Some(piece.clone()),
+            piece: Some(piece),
         }
     }
 
@@ -109,15 +109,10 @@ impl<'a> PieceButton<'a> {
         }
     }
 
-    pub fn is_promoted(&self) -> bool {
-        let p = self.piece.unwrap().piece_type;
-        p == PieceType::ProPawn || p == PieceType::ProKnight || p == PieceType::ProLance || p == PieceType::ProSilver || p == PieceType::ProRook || p == PieceType::ProBishop
-    }
-
     #[allow(dead_code)]
     pub fn promoted_piecetype(&self) -> PieceType {
         let p = self.piece.unwrap().piece_type;
-        return match p {
+        match p {
             PieceType::Silver => PieceType::ProSilver,
             PieceType::Knight => PieceType::ProKnight,
             PieceType::Lance => PieceType::ProLance,
@@ -152,7 +147,7 @@ impl Clone for PieceButton<'_> {
     fn clone(&self) -> Self {
         PieceButton {
             button: self.button.clone(),
-            piece: self.piece.clone(),
+            piece: self.piece,
         }
     }
 }
\ No newline at end of file
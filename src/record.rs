@@ -0,0 +1,609 @@
+use shogi::{Move, Position, SfenError};
+
+use crate::rules;
+
+pub const STARTPOS_SFEN: &str = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+
+// How a game record ends, written after the last move
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameEnd {
+    Resign,     // Side to move resigned
+    Interrupt,
+    Sennichite,
+    Jishogi,    // Impasse draw
+    Checkmate,  // Side to move is mated
+    TimeUp,     // Side to move lost on time
+    IllegalWin, // Previous move was illegal, side to move wins
+    IllegalLoss,
+    DeclareWin, // Side to move declared an entering king win
+    MaxMoves,   // Move limit reached, a draw. KIF has no word for it and uses 中断
+}
+
+impl GameEnd {
+    // Interrupt comes before MaxMoves so 中断 is read back as Interrupt
+    pub const ALL: [GameEnd; 10] = [
+        GameEnd::Resign, GameEnd::Interrupt, GameEnd::Sennichite, GameEnd::Jishogi, GameEnd::Checkmate,
+        GameEnd::TimeUp, GameEnd::IllegalWin, GameEnd::IllegalLoss, GameEnd::DeclareWin, GameEnd::MaxMoves,
+    ];
+
+    pub fn kif_name(self) -> &'static str {
+        match self {
+            GameEnd::Resign      => "投了",
+            GameEnd::Interrupt   => "中断",
+            GameEnd::Sennichite  => "千日手",
+            GameEnd::Jishogi     => "持将棋",
+            GameEnd::Checkmate   => "詰み",
+            GameEnd::TimeUp      => "切れ負け",
+            GameEnd::IllegalWin  => "反則勝ち",
+            GameEnd::IllegalLoss => "反則負け",
+            GameEnd::DeclareWin  => "入玉勝ち",
+            GameEnd::MaxMoves    => "中断",
+        }
+    }
+
+    pub fn from_kif(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|end| end.kif_name() == name)
+    }
+
+    // Some(true) if the side to move after the last move wins, None for draws and interrupted games
+    pub fn side_to_move_wins(self) -> Option<bool> {
+        match self {
+            GameEnd::IllegalWin | GameEnd::DeclareWin => Some(true),
+            GameEnd::Resign | GameEnd::Checkmate | GameEnd::TimeUp | GameEnd::IllegalLoss => Some(false),
+            GameEnd::Interrupt | GameEnd::Sennichite | GameEnd::Jishogi | GameEnd::MaxMoves => None,
+        }
+    }
+}
+
+
+// Evaluation glyph attached to a move, written to game records as a comment line holding only the symbol
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Glyph {
+    Good,
+    Mistake,
+    Brilliant,
+    Blunder,
+    Interesting,
+    Dubious,
+}
+
+impl Glyph {
+    pub const ALL: [Glyph; 6] = [Glyph::Good, Glyph::Mistake, Glyph::Brilliant, Glyph::Blunder, Glyph::Interesting, Glyph::Dubious];
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Glyph::Good        => "!",
+            Glyph::Mistake     => "?",
+            Glyph::Brilliant   => "!!",
+            Glyph::Blunder     => "??",
+            Glyph::Interesting => "!?",
+            Glyph::Dubious     => "?!",
+        }
+    }
+
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|glyph| glyph.symbol() == symbol.trim())
+    }
+}
+
+// A move with what was recorded about it
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecordMove {
+    pub mv: Move,
+    pub time_secs: Option<u32>, // Time spent on the move
+    pub glyph: Option<Glyph>,
+    pub comments: Vec<String>,
+    pub variations: Vec<Variation>, // Lines playing a different move at this ply
+}
+
+impl RecordMove {
+    fn new(mv: Move, time_secs: Option<u32>) -> Self {
+        Self { mv, time_secs, glyph: None, comments: Vec::new(), variations: Vec::new() }
+    }
+
+    // Comment lines as written in game records, the glyph comes first
+    pub fn comment_lines(&self) -> impl Iterator<Item = &str> {
+        self.glyph.map(Glyph::symbol).into_iter().chain(self.comments.iter().map(String::as_str))
+    }
+}
+
+// Line branching off the game, its first move replaces the move it hangs from.
+// A variation without moves is a result recorded in place of the move, see end_at_cursor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variation {
+    pub moves: Vec<RecordMove>,
+    pub end: Option<GameEnd>,
+}
+
+// Start position and moves of the game, the board and the engine's "position" command are built from it.
+// Moves form a tree, the main line is in moves and every variation hangs from the move it replaces.
+// The board follows the line picked by path up to the cursor, moves after the cursor can be redone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameRecord {
+    pub header: Vec<(String, String)>, // KIF header such as 先手/後手/開始日時, in file order
+    pub start_sfen: String, // Without moves
+    pub start_comments: Vec<String>,
+    pub moves: Vec<RecordMove>, // Main line
+    pub end: Option<GameEnd>, // After the last move of the main line
+    pub path: Vec<(usize, usize)>, // Variations taken as (ply, index), ply counts from 0 and increases
+    pub cursor: usize, // Number of moves on the board
+}
+
+impl GameRecord {
+    pub fn new(start_sfen: &str) -> Self {
+        Self {
+            header: Vec::new(),
+            start_sfen: start_sfen.to_string(),
+            start_comments: Vec::new(),
+            moves: Vec::new(),
+            end: None,
+            path: Vec::new(),
+            cursor: 0,
+        }
+    }
+
+    // "<sfen> [moves ...]" with every move played, fails if a move is illegal
+    pub fn from_sfen(sfen: &str) -> Result<Self, SfenError> {
+        rules::position_from_sfen(sfen)?;
+
+        let (start, moves) = match sfen.split_once(" moves") {
+            Some((start, moves)) => (start, moves),
+            None => (sfen, ""),
+        };
+        let mut record = Self::new(start.trim());
+        for m in moves.split_whitespace().filter_map(rules::parse_move) {
+            record.play(m, None);
+        }
+        Ok(record)
+    }
+
+    // Line reached by taking the first depth variations of path, with the ply of its first move
+    fn branch(&self, depth: usize) -> (usize, &Vec<RecordMove>, Option<GameEnd>) {
+        let (mut start, mut moves, mut end) = (0, &self.moves, self.end);
+        for &(ply, index) in &self.path[..depth] {
+            let variation = &moves[ply - start].variations[index];
+            (start, moves, end) = (ply, &variation.moves, variation.end);
+        }
+        (start, moves, end)
+    }
+
+    fn branch_mut(&mut self, depth: usize) -> (usize, &mut Vec<RecordMove>, &mut Option<GameEnd>) {
+        let (mut start, mut moves, mut end) = (0, &mut self.moves, &mut self.end);
+        for &(ply, index) in &self.path[..depth] {
+            let variation = &mut moves[ply - start].variations[index];
+            (start, moves, end) = (ply, &mut variation.moves, &mut variation.end);
+        }
+        (start, moves, end)
+    }
+
+    // Number of variations in path that branch off before ply
+    fn depth_before(&self, ply: usize) -> usize {
+        self.path.iter().take_while(|&&(branch_ply, _)| branch_ply < ply).count()
+    }
+
+    // Every move of the line followed by path, including the ones after the cursor
+    pub fn line(&self) -> Vec<&RecordMove> {
+        let mut line = Vec::new();
+        let (mut start, mut moves) = (0, &self.moves);
+        for &(ply, index) in &self.path {
+            line.extend(&moves[..ply - start]);
+            moves = &moves[ply - start].variations[index].moves;
+            start = ply;
+        }
+        line.extend(moves);
+        line
+    }
+
+    // How the line followed by path ends
+    pub fn line_end(&self) -> Option<GameEnd> {
+        self.branch(self.path.len()).2
+    }
+
+    pub fn set_line_end(&mut self, end: Option<GameEnd>) {
+        *self.branch_mut(self.path.len()).2 = end;
+    }
+
+    // End the line at the cursor. When moves after it were taken back, they stay in the record and the result
+    // goes on a new branch at the cursor, a variation without moves that the board then follows.
+    pub fn end_at_cursor(&mut self, end: GameEnd) {
+        if !self.can_redo() {
+            self.set_line_end(Some(end));
+            return;
+        }
+        let cursor = self.cursor;
+        let depth = self.depth_before(cursor);
+        self.path.truncate(depth);
+        let (start, moves, _) = self.branch_mut(depth);
+        let variations = &mut moves[cursor - start].variations;
+        let index = match variations.iter().position(|v| v.moves.is_empty() && v.end == Some(end)) {
+            Some(index) => index,
+            None => {
+                variations.push(Variation { moves: Vec::new(), end: Some(end) });
+                variations.len() - 1
+            }
+        };
+        self.path.push((cursor, index));
+    }
+
+    // The board follows a result recorded in place of the move at the cursor
+    fn on_result_branch(&self) -> bool {
+        self.path.last().is_some_and(|&(ply, _)| ply == self.cursor) && !self.can_redo()
+    }
+
+    // Start position followed by the moves up to the cursor, as sent with "position sfen"
+    pub fn sfen(&self) -> String {
+        if self.cursor == 0 {
+            return self.start_sfen.clone();
+        }
+        let moves: Vec<String> = self.line()[..self.cursor].iter().map(|m| m.mv.to_string()).collect();
+        format!("{} moves {}", self.start_sfen, moves.join(" "))
+    }
+
+    // Board at the cursor
+    pub fn position(&self) -> Result<Position, SfenError> {
+        rules::position_from_sfen(&self.sfen())
+    }
+
+    // Move leading to the position at the cursor
+    pub fn last_move(&self) -> Option<Move> {
+        self.cursor.checked_sub(1).map(|i| self.line()[i].mv)
+    }
+
+    // The move leading to the position at the cursor, for attaching comments and times
+    pub fn last_move_mut(&mut self) -> Option<&mut RecordMove> {
+        let ply = self.cursor.checked_sub(1)?;
+        let depth = self.depth_before(ply + 1);
+        let (start, moves, _) = self.branch_mut(depth);
+        moves.get_mut(ply - start)
+    }
+
+    // Comments on the position at the cursor, written after the move leading to it or before the first move
+    pub fn comments(&self) -> &[String] {
+        match self.cursor.checked_sub(1) {
+            Some(ply) => &self.line()[ply].comments,
+            None => &self.start_comments,
+        }
+    }
+
+    pub fn set_comments(&mut self, comments: Vec<String>) {
+        match self.last_move_mut() {
+            Some(last) => last.comments = comments,
+            None => self.start_comments = comments,
+        }
+    }
+
+    // Attach a comment line read from a game record, a line holding only a glyph sets the glyph of the move
+    pub fn add_comment(&mut self, comment: String) {
+        let glyph = Glyph::from_symbol(&comment);
+        match self.last_move_mut() {
+            Some(last) if glyph.is_some() => last.glyph = glyph,
+            Some(last) => last.comments.push(comment),
+            None => self.start_comments.push(comment),
+        }
+    }
+
+    pub fn glyph(&self) -> Option<Glyph> {
+        self.cursor.checked_sub(1).and_then(|ply| self.line()[ply].glyph)
+    }
+
+    pub fn set_glyph(&mut self, glyph: Option<Glyph>) {
+        if let Some(last) = self.last_move_mut() {
+            last.glyph = glyph;
+        }
+    }
+
+    // Every move of the line in USI notation including undone ones
+    pub fn usi_moves(&self) -> Vec<String> {
+        self.line().iter().map(|m| m.mv.to_string()).collect()
+    }
+
+    pub fn header_value(&self, key: &str) -> Option<&str> {
+        self.header.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    pub fn set_header(&mut self, key: &str, value: String) {
+        match self.header.iter_mut().find(|(k, _)| k == key) {
+            Some(entry) => entry.1 = value,
+            None => self.header.push((key.to_string(), value)),
+        }
+    }
+
+    // Play m at the cursor. A move already recorded at this ply is followed again, keeping the moves after it,
+    // any other move starts a new variation unless the cursor is at the end of the line.
+    pub fn play(&mut self, m: Move, time_secs: Option<u32>) {
+        let ply = self.cursor;
+        self.cursor += 1;
+        if self.line().get(ply).map(|next| next.mv) == Some(m) {
+            return;
+        }
+
+        let depth = self.depth_before(ply);
+        self.path.truncate(depth);
+        let (start, moves, end) = self.branch_mut(depth);
+        let Some(replaced) = moves.get_mut(ply - start) else {
+            moves.push(RecordMove::new(m, time_secs));
+            *end = None;
+            return;
+        };
+        if replaced.mv == m {
+            return;
+        }
+        let index = match replaced.variations.iter().position(|v| v.moves.first().map(|rm| rm.mv) == Some(m)) {
+            Some(index) => index,
+            None => {
+                replaced.variations.push(Variation { moves: vec![RecordMove::new(m, time_secs)], end: None });
+                replaced.variations.len() - 1
+            }
+        };
+        self.path.push((ply, index));
+    }
+
+    // Moves recorded at ply after the line's earlier moves, starting with the one of the line the others branch off.
+    // A result recorded in place of a move is Err.
+    pub fn alternatives(&self, ply: usize) -> Vec<Result<Move, GameEnd>> {
+        let (start, moves, _) = self.branch(self.depth_before(ply));
+        match moves.get(ply - start) {
+            Some(rm) => {
+                let variations = rm.variations.iter().map(|v| v.moves.first().map(|first| first.mv).ok_or(v.end.unwrap_or(GameEnd::Interrupt)));
+                std::iter::once(Ok(rm.mv)).chain(variations).collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    // Follow alternative index at ply as returned by alternatives, the board shows its move or the position its result follows
+    pub fn choose(&mut self, ply: usize, index: usize) {
+        self.path.truncate(self.depth_before(ply));
+        if index > 0 {
+            self.path.push((ply, index - 1));
+        }
+        self.cursor = (ply + 1).min(self.line().len());
+    }
+
+    // The move at the cursor is on a variation rather than the main line
+    pub fn in_variation(&self) -> bool {
+        self.depth_before(self.cursor) > 0 || self.on_result_branch()
+    }
+
+    // Make the line followed by path the main line, the lines it replaces become its first variations.
+    // A result branch has no move to hang the replaced line from, so it stays a variation of the promoted line.
+    pub fn promote_line(&mut self) {
+        let result = if self.on_result_branch() { self.path.pop() } else { None };
+        while let Some((ply, index)) = self.path.pop() {
+            let (start, moves, end) = self.branch_mut(self.path.len());
+            let mut replaced = moves.split_off(ply - start);
+            let mut variations = std::mem::take(&mut replaced[0].variations);
+            let promoted = variations.remove(index);
+            variations.insert(0, Variation { moves: replaced, end: end.take() });
+
+            moves.extend(promoted.moves);
+            moves[ply - start].variations = variations;
+            *end = promoted.end;
+        }
+        self.path.extend(result);
+    }
+
+    // Delete the variation holding the move at the cursor, the board goes back to where it branched off
+    pub fn delete_variation(&mut self) -> bool {
+        let depth = if self.on_result_branch() { self.path.len() } else { self.depth_before(self.cursor) };
+        if depth == 0 {
+            return false;
+        }
+        let (ply, index) = self.path[depth - 1];
+        self.path.truncate(depth - 1);
+        let (start, moves, _) = self.branch_mut(depth - 1);
+        moves[ply - start].variations.remove(index);
+        self.cursor = ply;
+        true
+    }
+
+    pub fn can_undo(&self) -> bool {
+        self.cursor > 0
+    }
+
+    pub fn can_redo(&self) -> bool {
+        self.cursor < self.line().len()
+    }
+
+    pub fn undo(&mut self) -> bool {
+        if !self.can_undo() {
+            return false;
+        }
+        self.cursor -= 1;
+        true
+    }
+
+    pub fn redo(&mut self) -> bool {
+        if !self.can_redo() {
+            return false;
+        }
+        self.cursor += 1;
+        true
+    }
+
+    // Jump to the position after ply moves of the line
+    pub fn seek(&mut self, ply: usize) {
+        self.cursor = ply.min(self.line().len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(moves: &[&str]) -> GameRecord {
+        let mut record = GameRecord::new(STARTPOS_SFEN);
+        for usi in moves {
+            record.play(rules::parse_move(usi).unwrap(), None);
+        }
+        record
+    }
+
+    fn usi(moves: &[RecordMove]) -> Vec<String> {
+        moves.iter().map(|rm| rm.mv.to_string()).collect()
+    }
+
+    fn mv(usi: &str) -> Move {
+        rules::parse_move(usi).unwrap()
+    }
+
+    // Main line 7g7f 3c3d 2g2f 8c8d 2f2e with the variation 8c8d 2g2f at the second move, followed by path
+    fn branched() -> GameRecord {
+        let mut record = record(&["7g7f", "3c3d", "2g2f", "8c8d", "2f2e"]);
+        record.end = Some(GameEnd::Resign);
+        record.seek(1);
+        record.play(mv("8c8d"), None);
+        record.play(mv("2g2f"), None);
+        record
+    }
+
+    #[test]
+    fn play_at_an_earlier_ply_starts_a_variation() {
+        let record = branched();
+
+        assert_eq!(usi(&record.moves), ["7g7f", "3c3d", "2g2f", "8c8d", "2f2e"]);
+        assert_eq!(record.end, Some(GameEnd::Resign));
+        assert_eq!(usi(&record.moves[1].variations[0].moves), ["8c8d", "2g2f"]);
+        assert_eq!(record.moves[1].variations[0].end, None);
+        assert_eq!(record.path, [(1, 0)]);
+        assert_eq!(record.cursor, 3);
+        assert_eq!(record.usi_moves(), ["7g7f", "8c8d", "2g2f"]);
+        assert_eq!(record.alternatives(1), [Ok(mv("3c3d")), Ok(mv("8c8d"))]);
+        assert!(record.in_variation());
+        assert!(!record.can_redo());
+    }
+
+    #[test]
+    fn play_follows_recorded_moves() {
+        let mut record = branched();
+        record.seek(1);
+        record.play(mv("8c8d"), None);
+        assert_eq!(record.path, [(1, 0)]);
+        assert_eq!(record.moves[1].variations.len(), 1);
+
+        record.seek(1);
+        record.play(mv("3c3d"), None);
+        assert!(record.path.is_empty());
+        assert_eq!(record.cursor, 2);
+        assert_eq!(record.usi_moves(), ["7g7f", "3c3d", "2g2f", "8c8d", "2f2e"]);
+    }
+
+    #[test]
+    fn choose_switches_lines() {
+        let mut record = branched();
+        record.choose(1, 0);
+        assert!(record.path.is_empty());
+        assert_eq!(record.cursor, 2);
+        assert_eq!(record.line_end(), Some(GameEnd::Resign));
+        assert!(!record.in_variation());
+
+        record.choose(1, 1);
+        assert_eq!(record.path, [(1, 0)]);
+        assert_eq!(record.cursor, 2);
+        assert_eq!(record.last_move(), Some(mv("8c8d")));
+        assert_eq!(record.line_end(), None);
+    }
+
+    #[test]
+    fn promote_line_swaps_main_line_and_variation() {
+        let mut record = branched();
+        record.promote_line();
+
+        assert!(record.path.is_empty());
+        assert_eq!(record.cursor, 3);
+        assert_eq!(usi(&record.moves), ["7g7f", "8c8d", "2g2f"]);
+        assert_eq!(record.end, None);
+        let demoted = &record.moves[1].variations[0];
+        assert_eq!(usi(&demoted.moves), ["3c3d", "2g2f", "8c8d", "2f2e"]);
+        assert_eq!(demoted.end, Some(GameEnd::Resign));
+        assert_eq!(record.alternatives(1), [Ok(mv("8c8d")), Ok(mv("3c3d"))]);
+    }
+
+    #[test]
+    fn delete_variation_returns_to_the_branch() {
+        let mut record = branched();
+        assert!(record.delete_variation());
+
+        assert!(record.path.is_empty());
+        assert_eq!(record.cursor, 1);
+        assert!(record.moves[1].variations.is_empty());
+        assert_eq!(record.usi_moves(), ["7g7f", "3c3d", "2g2f", "8c8d", "2f2e"]);
+        // The main line cannot be deleted
+        assert!(!record.delete_variation());
+    }
+
+    #[test]
+    fn sfen_follows_the_branch() {
+        shogi::bitboard::Factory::init();
+        let mut record = branched();
+        assert_eq!(record.sfen(), format!("{} moves 7g7f 8c8d 2g2f", STARTPOS_SFEN));
+        assert_eq!(rules::board_sfen(&record.position().unwrap()), "lnsgkgsnl/1r5b1/p1ppppppp/1p7/9/2P4P1/PP1PPPP1P/1B5R1/LNSGKGSNL w - 4");
+
+        record.undo();
+        assert_eq!(record.sfen(), format!("{} moves 7g7f 8c8d", STARTPOS_SFEN));
+        record.seek(0);
+        assert_eq!(record.sfen(), STARTPOS_SFEN);
+    }
+
+    #[test]
+    fn end_at_the_end_of_the_line() {
+        let mut record = branched();
+        record.end_at_cursor(GameEnd::Resign);
+        assert_eq!(record.path, [(1, 0)]);
+        assert_eq!(record.moves[1].variations[0].end, Some(GameEnd::Resign));
+        assert_eq!(record.end, Some(GameEnd::Resign));
+    }
+
+    #[test]
+    fn end_before_taken_back_moves_keeps_them() {
+        let mut record = branched();
+        record.choose(1, 0);
+        record.end_at_cursor(GameEnd::DeclareWin);
+
+        // The result branches off at the third move, the main line is untouched
+        assert_eq!(usi(&record.moves), ["7g7f", "3c3d", "2g2f", "8c8d", "2f2e"]);
+        assert_eq!(record.end, Some(GameEnd::Resign));
+        assert_eq!(record.moves[2].variations, [Variation { moves: Vec::new(), end: Some(GameEnd::DeclareWin) }]);
+        assert_eq!(record.path, [(2, 0)]);
+        assert_eq!(record.cursor, 2);
+        assert_eq!(record.line_end(), Some(GameEnd::DeclareWin));
+        assert!(!record.can_redo());
+        assert!(record.in_variation());
+        assert_eq!(record.alternatives(2), [Ok(mv("2g2f")), Err(GameEnd::DeclareWin)]);
+
+        // Ending there again reuses the branch
+        record.choose(2, 0);
+        record.undo();
+        record.end_at_cursor(GameEnd::DeclareWin);
+        assert_eq!(record.moves[2].variations.len(), 1);
+        assert_eq!(record.path, [(2, 0)]);
+
+        // Making it the main line keeps the moves, the result stays a branch
+        record.promote_line();
+        assert_eq!(record.path, [(2, 0)]);
+        assert_eq!(usi(&record.moves), ["7g7f", "3c3d", "2g2f", "8c8d", "2f2e"]);
+
+        record.choose(2, 0);
+        assert_eq!(record.cursor, 3);
+        record.choose(2, 1);
+        assert_eq!(record.cursor, 2);
+        assert!(record.delete_variation());
+        assert!(record.path.is_empty());
+        assert!(record.moves[2].variations.is_empty());
+        assert_eq!(record.cursor, 2);
+    }
+
+    #[test]
+    fn play_leaves_a_result_branch() {
+        let mut record = branched();
+        record.choose(1, 0);
+        record.end_at_cursor(GameEnd::Resign);
+        record.undo();
+        record.play(mv("3c3d"), None);
+        assert_eq!(record.path, [(2, 0)]);
+        record.play(mv("6g6f"), None);
+        assert_eq!(record.path, [(2, 1)]);
+        assert_eq!(record.usi_moves(), ["7g7f", "3c3d", "6g6f"]);
+        assert_eq!(record.moves[2].variations[0].end, Some(GameEnd::Resign));
+    }
+}
@@ -0,0 +1,606 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use shogi::{Color, Move, MoveError, Piece, PieceType, Position, SfenError, Square};
+
+// Position is not Clone, so copies are rebuilt from SFEN.
+// Moves after "moves" are replayed with make_move because set_sfen logs them twice for repetition detection.
+pub fn position_from_sfen(sfen: &str) -> Result<Position, SfenError> {
+    let (base, moves) = match sfen.split_once(" moves") {
+        Some((base, moves)) => (base, moves),
+        None => (sfen, ""),
+    };
+
+    let mut pos = Position::new();
+    pos.set_sfen(base)?;
+    for token in moves.split_whitespace() {
+        let m = parse_move(token).ok_or(SfenError::IllegalMove)?;
+        make_move(&mut pos, m).map_err(|_| SfenError::IllegalMove)?;
+    }
+    Ok(pos)
+}
+
+// Move in USI notation. Move::from_sfen slices bytes and panics on non-ASCII text, which engines and users can send.
+pub fn parse_move(text: &str) -> Option<Move> {
+    if !text.is_ascii() {
+        return None;
+    }
+    Move::from_sfen(text)
+}
+
+// make_move that accepts moves ending the game by repetition, the board is already updated when those are returned.
+// Position does not add such a move to its move history though, so to_sfen and unmake_move are wrong afterwards:
+// positions are rebuilt from the game record's moves, and copies are made from the board with board_sfen.
+pub fn make_move(pos: &mut Position, m: Move) -> Result<(), MoveError> {
+    match pos.make_move(m) {
+        Err(MoveError::Repetition | MoveError::PerpetualCheckWin | MoveError::PerpetualCheckLose) => Ok(()),
+        res => res,
+    }
+}
+
+// SFEN of the board, hands, side to move and move number without the moves leading there,
+// unlike Position::to_sfen it does not depend on the move history
+pub fn board_sfen(pos: &Position) -> String {
+    let rows: Vec<String> = (0..9).map(|rank| {
+        let mut row = String::new();
+        let mut empty = 0;
+        for file in (0..9).rev() {
+            match *pos.piece_at(Square::new(file, rank).unwrap()) {
+                Some(piece) => {
+                    if empty > 0 {
+                        row.push_str(&empty.to_string());
+                        empty = 0;
+                    }
+                    row.push_str(&piece.to_string());
+                }
+                None => empty += 1,
+            }
+        }
+        if empty > 0 {
+            row.push_str(&empty.to_string());
+        }
+        row
+    }).collect();
+
+    let mut hand = String::new();
+    for color in [Color::Black, Color::White] {
+        for piece_type in PieceType::iter().filter(|piece_type| piece_type.is_hand_piece()) {
+            let piece = Piece { piece_type, color };
+            match pos.hand(piece) {
+                0 => (),
+                1 => hand.push_str(&piece.to_string()),
+                n => hand.push_str(&format!("{}{}", n, piece)),
+            }
+        }
+    }
+    if hand.is_empty() {
+        hand.push('-');
+    }
+
+    let side = match pos.side_to_move() {
+        Color::Black => "b",
+        Color::White => "w",
+    };
+    format!("{} {} {} {}", rows.join("/"), side, hand, pos.ply())
+}
+
+// How a fourfold repetition ends the game
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Repetition {
+    Draw,
+    PerpetualCheck(Color), // Side that gave check with every move since the first of the four positions, it loses
+}
+
+// Key of a position for repetition: board, hands and side to move, without the move number
+fn position_hash(pos: &Position) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for sq in Square::iter() {
+        pos.piece_at(sq).map(|piece| (piece.piece_type.index(), piece.color.index())).hash(&mut hasher);
+    }
+    for color in [Color::Black, Color::White] {
+        for piece_type in PieceType::iter().filter(|piece_type| piece_type.is_hand_piece()) {
+            pos.hand(Piece { piece_type, color }).hash(&mut hasher);
+        }
+    }
+    pos.side_to_move().index().hash(&mut hasher);
+    hasher.finish()
+}
+
+// Checks whether the last position of "<sfen> moves ..." has now occurred four times
+pub fn detect_repetition(sfen: &str) -> Option<Repetition> {
+    let (base, moves) = sfen.split_once(" moves").unwrap_or((sfen, ""));
+    let mut pos = Position::new();
+    pos.set_sfen(base).ok()?;
+
+    // Hash of every position and whether its side to move is in check, i.e. the move leading to it gave check
+    let mut history = vec![(position_hash(&pos), false)];
+    for token in moves.split_whitespace() {
+        make_move(&mut pos, parse_move(token)?).ok()?;
+        history.push((position_hash(&pos), pos.in_check(pos.side_to_move())));
+    }
+
+    let (last, _) = *history.last()?;
+    let occurrences: Vec<usize> = history.iter().enumerate().filter(|(_, (hash, _))| *hash == last).map(|(i, _)| i).collect();
+    if occurrences.len() < 4 {
+        return None;
+    }
+
+    // Moves since the first of the four positions, the last move was made by the side not to move
+    let first = occurrences[occurrences.len() - 4];
+    let last_mover = pos.side_to_move().flip();
+    let checked_throughout = |color: Color| {
+        history[first + 1..].iter().rev().enumerate()
+            .filter(|(i, _)| (if i % 2 == 0 { last_mover } else { last_mover.flip() }) == color)
+            .all(|(_, (_, check))| *check)
+    };
+    match (checked_throughout(Color::Black), checked_throughout(Color::White)) {
+        (true, false) => Some(Repetition::PerpetualCheck(Color::Black)),
+        (false, true) => Some(Repetition::PerpetualCheck(Color::White)),
+        _             => Some(Repetition::Draw),
+    }
+}
+
+// Point counting for an entering king declaration (nyugyoku sengen)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImpasseRule {
+    Points24, // 31 points win, 24 to 30 points draw
+    Points27, // Black needs 28 points and White 27 to win, the CSA rule most engines follow
+}
+
+impl ImpasseRule {
+    pub const ALL: [ImpasseRule; 2] = [ImpasseRule::Points24, ImpasseRule::Points27];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ImpasseRule::Points24 => "24 points",
+            ImpasseRule::Points27 => "27 points",
+        }
+    }
+}
+
+// Outcome of an entering king declaration by the side to move
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Declaration {
+    Win,
+    Draw,
+    Invalid(&'static str), // Condition that is not met, declaring anyway loses
+}
+
+// 5 points for rooks and bishops, promoted or not, 1 for every other piece but the king
+fn piece_points(piece_type: PieceType) -> u32 {
+    match piece_type {
+        PieceType::Rook | PieceType::Bishop | PieceType::ProRook | PieceType::ProBishop => 5,
+        PieceType::King => 0,
+        _ => 1,
+    }
+}
+
+// Points of color's pieces in the enemy camp and in hand, and how many pieces other than the king are in the camp
+pub fn impasse_points(pos: &Position, color: Color) -> (u32, u32) {
+    let mut points = 0;
+    let mut pieces = 0;
+    for sq in Square::iter().filter(|sq| sq.in_promotion_zone(color)) {
+        if let Some(piece) = *pos.piece_at(sq) {
+            if piece.color == color && piece.piece_type != PieceType::King {
+                points += piece_points(piece.piece_type);
+                pieces += 1;
+            }
+        }
+    }
+    for piece_type in PieceType::iter().filter(|piece_type| piece_type.is_hand_piece()) {
+        points += piece_points(piece_type) * pos.hand(Piece { piece_type, color }) as u32;
+    }
+    (points, pieces)
+}
+
+// Declaration conditions: king in the enemy camp, not in check, 10 other pieces there and enough points
+pub fn declaration(pos: &Position, rule: ImpasseRule) -> Declaration {
+    let color = pos.side_to_move();
+    if !pos.find_king(color).is_some_and(|sq| sq.in_promotion_zone(color)) {
+        return Declaration::Invalid("the king has not entered the enemy camp");
+    }
+    if pos.in_check(color) {
+        return Declaration::Invalid("the king is in check");
+    }
+    let (points, pieces) = impasse_points(pos, color);
+    if pieces < 10 {
+        return Declaration::Invalid("fewer than 10 pieces are in the enemy camp");
+    }
+    match rule {
+        ImpasseRule::Points24 if points >= 31 => Declaration::Win,
+        ImpasseRule::Points24 if points >= 24 => Declaration::Draw,
+        ImpasseRule::Points24 => Declaration::Invalid("fewer than 24 points"),
+        ImpasseRule::Points27 if points >= if color == Color::Black { 28 } else { 27 } => Declaration::Win,
+        ImpasseRule::Points27 => Declaration::Invalid("not enough points"),
+    }
+}
+
+// Side to move of a "<board> <side> <hand> <ply> [moves ...]" string without building the position
+pub fn sfen_side_to_move(sfen: &str) -> Color {
+    let base = if sfen.split_whitespace().nth(1) == Some("w") { Color::White } else { Color::Black };
+    let moves = sfen.split_whitespace().skip_while(|token| *token != "moves").skip(1).count();
+    if moves % 2 == 1 { base.flip() } else { base }
+}
+
+pub fn destination(m: Move) -> Square {
+    match m {
+        Move::Normal { to, .. } | Move::Drop { to, .. } => to,
+    }
+}
+
+// Squares holding piece that can move to `to`, ignoring checks
+pub fn pieces_reaching(pos: &Position, piece: Piece, to: Square) -> Vec<Square> {
+    Square::iter()
+        .filter(|sq| *pos.piece_at(*sq) == Some(piece))
+        .filter(|sq| pos.move_candidates(*sq, piece).into_iter().any(|target| target == to))
+        .collect()
+}
+
+// Unpromoted pieces other than gold and king may promote when moving into, out of or within the promotion zone
+pub fn can_promote(piece: Piece, from: Square, to: Square) -> bool {
+    piece.promote().is_some() && (from.in_promotion_zone(piece.color) || to.in_promotion_zone(piece.color))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Promotion {
+    Never,
+    Optional,
+    Forced, // Pawns and lances on the last rank and knights on the last two ranks could never move again
+}
+
+// Promotion choice for a piece moving between two squares, the zone is judged from both of them
+pub fn promotion(piece: Piece, from: Square, to: Square) -> Promotion {
+    if !can_promote(piece, from, to) {
+        Promotion::Never
+    }
+    else if !piece.is_placeable_at(to) {
+        Promotion::Forced
+    }
+    else {
+        Promotion::Optional
+    }
+}
+
+// Every move Position::make_move accepts for the side to move
+pub fn legal_moves(pos: &Position) -> Vec<Move> {
+    let mut moves = Vec::new();
+    try_candidates(pos, |m| {
+        moves.push(m);
+        false
+    });
+    moves
+}
+
+// Stops at the first legal move, used for mate detection
+pub fn has_legal_move(pos: &Position) -> bool {
+    let mut found = false;
+    try_candidates(pos, |_| {
+        found = true;
+        true
+    });
+    found
+}
+
+// Calls on_legal for each legal move until it returns true.
+// The copy starts from the board without move history, so repetitions are left to detect_repetition.
+fn try_candidates(pos: &Position, mut on_legal: impl FnMut(Move) -> bool) {
+    let Ok(mut scratch) = position_from_sfen(&board_sfen(pos)) else {
+        return;
+    };
+
+    for m in pseudo_legal_moves(pos) {
+        if scratch.make_move(m).is_ok() {
+            let _ = scratch.unmake_move();
+            if on_legal(m) {
+                return;
+            }
+        }
+    }
+}
+
+// Moves following piece movement, promotion and drop placement rules, ignoring checks, nifu and uchifuzume
+fn pseudo_legal_moves(pos: &Position) -> Vec<Move> {
+    let stm = pos.side_to_move();
+    let mut moves = Vec::new();
+
+    for from in Square::iter() {
+        let Some(piece) = *pos.piece_at(from) else {
+            continue;
+        };
+        if piece.color != stm {
+            continue;
+        }
+        for to in pos.move_candidates(from, piece) {
+            let promotion = promotion(piece, from, to);
+            if promotion != Promotion::Never {
+                moves.push(Move::Normal { from, to, promote: true });
+            }
+            if promotion != Promotion::Forced {
+                moves.push(Move::Normal { from, to, promote: false });
+            }
+        }
+    }
+
+    for piece_type in PieceType::iter().filter(|pt| pt.is_hand_piece()) {
+        let piece = Piece { piece_type, color: stm };
+        if pos.hand(piece) == 0 {
+            continue;
+        }
+        for to in Square::iter() {
+            if pos.piece_at(to).is_none() && piece.is_placeable_at(to) {
+                moves.push(Move::Drop { to, piece_type });
+            }
+        }
+    }
+
+    moves
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Square on file 5 at a rank counted from the side of color's opponent, 0 is the last rank for color
+    fn relative(color: Color, rank: u8) -> Square {
+        match color {
+            Color::Black => Square::new(4, rank).unwrap(),
+            Color::White => Square::new(4, 8 - rank).unwrap(),
+        }
+    }
+
+    fn promotable(piece_type: PieceType) -> bool {
+        matches!(piece_type, PieceType::Pawn | PieceType::Lance | PieceType::Knight | PieceType::Silver | PieceType::Bishop | PieceType::Rook)
+    }
+
+    #[test]
+    fn can_promote_judges_origin_and_destination() {
+        // (from, to, touches the zone) in relative ranks
+        let moves = [
+            (3, 2, true),  // into the zone
+            (8, 0, true),  // into the zone from the far end
+            (2, 3, true),  // out of the zone
+            (0, 8, true),  // out of the zone to the far end
+            (1, 2, true),  // within the zone
+            (2, 0, true),  // within the zone to the last rank
+            (4, 3, false),
+            (3, 4, false),
+            (8, 3, false),
+            (5, 8, false),
+        ];
+        for color in [Color::Black, Color::White] {
+            for piece_type in PieceType::iter() {
+                let piece = Piece { piece_type, color };
+                for (from, to, zone) in moves {
+                    let expected = promotable(piece_type) && zone;
+                    assert_eq!(can_promote(piece, relative(color, from), relative(color, to)), expected, "{:?} {:?} {} to {}", color, piece_type, from, to);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn promotion_never_for_gold_king_and_promoted_pieces() {
+        for color in [Color::Black, Color::White] {
+            for piece_type in PieceType::iter().filter(|piece_type| !promotable(*piece_type)) {
+                let piece = Piece { piece_type, color };
+                for (from, to) in [(3, 2), (2, 3), (1, 0), (0, 1), (4, 3)] {
+                    assert_eq!(promotion(piece, relative(color, from), relative(color, to)), Promotion::Never, "{:?} {:?}", color, piece_type);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn promotion_outside_the_zone_is_never() {
+        for color in [Color::Black, Color::White] {
+            for piece_type in PieceType::iter() {
+                let piece = Piece { piece_type, color };
+                assert_eq!(promotion(piece, relative(color, 4), relative(color, 3)), Promotion::Never);
+                assert_eq!(promotion(piece, relative(color, 8), relative(color, 5)), Promotion::Never);
+            }
+        }
+    }
+
+    #[test]
+    fn promotion_optional_into_out_of_and_within_the_zone() {
+        for color in [Color::Black, Color::White] {
+            for piece_type in [PieceType::Pawn, PieceType::Lance, PieceType::Knight, PieceType::Silver, PieceType::Bishop, PieceType::Rook] {
+                let piece = Piece { piece_type, color };
+                for (from, to) in [(3, 2), (4, 2), (2, 3), (1, 4), (2, 2), (8, 2)] {
+                    assert_eq!(promotion(piece, relative(color, from), relative(color, to)), Promotion::Optional, "{:?} {:?} {} to {}", color, piece_type, from, to);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn promotion_forced_on_the_last_ranks() {
+        for color in [Color::Black, Color::White] {
+            let piece = |piece_type| Piece { piece_type, color };
+            let last = |piece_type, from, to| promotion(piece(piece_type), relative(color, from), relative(color, to));
+
+            // Pawns and lances on the last rank
+            for piece_type in [PieceType::Pawn, PieceType::Lance] {
+                assert_eq!(last(piece_type, 1, 0), Promotion::Forced);
+                assert_eq!(last(piece_type, 3, 0), Promotion::Forced);
+                assert_eq!(last(piece_type, 2, 1), Promotion::Optional);
+            }
+            // Knights on the last two ranks
+            assert_eq!(last(PieceType::Knight, 2, 0), Promotion::Forced);
+            assert_eq!(last(PieceType::Knight, 3, 1), Promotion::Forced);
+            assert_eq!(last(PieceType::Knight, 4, 2), Promotion::Optional);
+            // Other pieces may stay unpromoted on the last rank
+            for piece_type in [PieceType::Silver, PieceType::Bishop, PieceType::Rook] {
+                assert_eq!(last(piece_type, 1, 0), Promotion::Optional);
+                assert_eq!(last(piece_type, 8, 0), Promotion::Optional);
+            }
+        }
+    }
+
+    fn usi_legal_moves(sfen: &str) -> Vec<String> {
+        shogi::bitboard::Factory::init();
+        legal_moves(&position_from_sfen(sfen).unwrap()).iter().map(|m| m.to_string()).collect()
+    }
+
+    #[test]
+    fn legal_moves_offer_both_choices_when_leaving_the_zone() {
+        // Black silver on 3c retreating, black bishop on 3c leaving along the diagonal
+        let silver = usi_legal_moves("4k4/9/6S2/9/9/9/9/9/4K4 b - 1");
+        let bishop = usi_legal_moves("4k4/9/6B2/9/9/9/9/9/4K4 b - 1");
+        // White silver on 7g retreating, white bishop on 7g leaving along the diagonal
+        let white_silver = usi_legal_moves("4k4/9/9/9/9/9/2s6/9/4K4 w - 1");
+        let white_bishop = usi_legal_moves("4k4/9/9/9/9/9/2b6/9/4K4 w - 1");
+
+        for (moves, m) in [
+            (&silver, "3c2d"),
+            (&silver, "3c4d"),
+            (&bishop, "3c7g"),
+            (&bishop, "3c9i"),
+            (&white_silver, "7g6f"),
+            (&white_silver, "7g8f"),
+            (&white_bishop, "7g3c"),
+            (&white_bishop, "7g1a"),
+        ] {
+            assert!(moves.contains(&m.to_string()), "{} missing", m);
+            assert!(moves.contains(&format!("{}+", m)), "{}+ missing", m);
+        }
+    }
+
+    #[test]
+    fn legal_moves_force_promotion_on_the_last_ranks() {
+        // Pawn on 3b, lance on 9c and knight on 8d
+        let moves = usi_legal_moves("4k4/6P2/L8/1N7/9/9/9/9/4K4 b - 1");
+        assert!(moves.contains(&String::from("3b3a+")));
+        assert!(!moves.contains(&String::from("3b3a")));
+        assert!(moves.contains(&String::from("9c9a+")));
+        assert!(!moves.contains(&String::from("9c9a")));
+        assert!(moves.contains(&String::from("9c9b+")));
+        assert!(moves.contains(&String::from("9c9b")));
+        for m in ["8d7b", "8d9b"] {
+            assert!(moves.contains(&format!("{}+", m)), "{}+ missing", m);
+            assert!(!moves.contains(&m.to_string()), "{} offered", m);
+        }
+    }
+
+    #[test]
+    fn drops_need_a_square_to_move_from() {
+        let black = usi_legal_moves("4k4/9/9/9/9/9/9/9/4K4 b PLN 1");
+        let white = usi_legal_moves("4k4/9/9/9/9/9/9/9/4K4 w pln 1");
+
+        // Pawns and lances not on the last rank, knights not on the last two
+        for (moves, dropped, kept) in [
+            (&black, ["P*1a", "L*1a", "N*1a", "N*1b"], ["P*1b", "L*1b", "N*1c"]),
+            (&white, ["P*9i", "L*9i", "N*9i", "N*9h"], ["P*9h", "L*9h", "N*9g"]),
+        ] {
+            for m in dropped {
+                assert!(!moves.contains(&m.to_string()), "{} offered", m);
+            }
+            for m in kept {
+                assert!(moves.contains(&m.to_string()), "{} missing", m);
+            }
+        }
+    }
+
+    #[test]
+    fn drops_exclude_nifu() {
+        // Unpromoted pawn on 5e, a tokin on 3e does not count
+        let moves = usi_legal_moves("4k4/9/9/9/4P1+P2/9/9/9/4K4 b P 1");
+        assert!(!moves.iter().any(|m| m.starts_with("P*5")));
+        assert!(moves.contains(&String::from("P*3f")));
+        assert!(moves.contains(&String::from("P*4e")));
+    }
+
+    #[test]
+    fn drops_exclude_uchifuzume() {
+        // White king on 1a, the gold on 2c guards 1b and 2b and the knight on 3c guards 2a
+        let mate = "8k/9/6NG1/9/9/9/9/9/4K4 b P 1";
+        let moves = usi_legal_moves(mate);
+        assert!(!moves.contains(&String::from("P*1b")));
+        assert!(moves.contains(&String::from("P*1c")));
+
+        // Without the knight the king escapes to 2a
+        assert!(usi_legal_moves("8k/9/7G1/9/9/9/9/9/4K4 b P 1").contains(&String::from("P*1b")));
+        // Mate by pushing a pawn is allowed
+        assert!(usi_legal_moves("8k/9/6NGP/9/9/9/9/9/4K4 b - 1").contains(&String::from("1c1b")));
+    }
+
+    // "<start> moves ..." with the cycle of moves played the given number of times
+    fn repeated(start: &str, cycle: &[&str], times: usize) -> String {
+        format!("{} moves {}", start, vec![cycle.join(" "); times].join(" "))
+    }
+
+    #[test]
+    fn fourfold_repetition_is_a_draw() {
+        shogi::bitboard::Factory::init();
+        // Both sides shuffle a gold back and forth, the start position comes back every four moves
+        let start = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1";
+        let cycle = ["6i6h", "4a4b", "6h6i", "4b4a"];
+        assert_eq!(detect_repetition(&repeated(start, &cycle, 2)), None);
+        assert_eq!(detect_repetition(&format!("{} {}", repeated(start, &cycle, 2), cycle[..3].join(" "))), None);
+        assert_eq!(detect_repetition(&repeated(start, &cycle, 3)), Some(Repetition::Draw));
+
+        // The fourth occurrence is still a legal move for the board
+        let mut pos = position_from_sfen(&format!("{} {}", repeated(start, &cycle, 2), cycle[..3].join(" "))).unwrap();
+        assert!(make_move(&mut pos, parse_move(cycle[3]).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn perpetual_check_loses_for_the_checker() {
+        shogi::bitboard::Factory::init();
+        // Black's rook checks from the 2 and 1 files while the white king steps between 2a and 1a
+        let start = "7k1/9/9/9/9/9/9/9/K7R b - 1";
+        let cycle = ["1i2i", "2a1a", "2i1i", "1a2a"];
+        assert_eq!(detect_repetition(&repeated(start, &cycle, 2)), None);
+        assert_eq!(detect_repetition(&repeated(start, &cycle, 3)), Some(Repetition::PerpetualCheck(Color::Black)));
+
+        // The same position turned around, white checks
+        let start = "r7k/9/9/9/9/9/9/9/1K7 w - 1";
+        let cycle = ["9a8a", "8i9i", "8a9a", "9i8i"];
+        assert_eq!(detect_repetition(&repeated(start, &cycle, 3)), Some(Repetition::PerpetualCheck(Color::White)));
+    }
+
+    // Black king on 5b with ten promoted pieces in the enemy camp worth 18 points, hand pieces make up the rest
+    fn entered(hand: &str) -> Position {
+        shogi::bitboard::Factory::init();
+        position_from_sfen(&format!("+R+B+P+P+P+P+P+P1/4K1+P+P1/9/9/9/9/9/9/4k4 b {} 1", hand)).unwrap()
+    }
+
+    #[test]
+    fn impasse_points_count_the_camp_and_the_hand() {
+        assert_eq!(impasse_points(&entered("RB3P"), Color::Black), (31, 10));
+        assert_eq!(impasse_points(&entered("RB3P"), Color::White), (0, 0));
+    }
+
+    #[test]
+    fn declaration_with_the_24_point_rule() {
+        assert_eq!(declaration(&entered("RB3P"), ImpasseRule::Points24), Declaration::Win);
+        assert_eq!(declaration(&entered("RB2P"), ImpasseRule::Points24), Declaration::Draw);
+        assert_eq!(declaration(&entered("BP"), ImpasseRule::Points24), Declaration::Draw);
+        assert_eq!(declaration(&entered("3P"), ImpasseRule::Points24), Declaration::Invalid("fewer than 24 points"));
+    }
+
+    #[test]
+    fn declaration_with_the_27_point_rule() {
+        assert_eq!(declaration(&entered("B5P"), ImpasseRule::Points27), Declaration::Win);
+        assert_eq!(declaration(&entered("B4P"), ImpasseRule::Points27), Declaration::Invalid("not enough points"));
+
+        // White needs a point less, the same position turned around
+        let white = position_from_sfen("4K4/9/9/9/9/9/9/1+p+p1k4/1+p+p+p+p+p+p+b+r w b4p 1").unwrap();
+        assert_eq!(impasse_points(&white, Color::White), (27, 10));
+        assert_eq!(declaration(&white, ImpasseRule::Points27), Declaration::Win);
+    }
+
+    #[test]
+    fn declaration_conditions() {
+        shogi::bitboard::Factory::init();
+        let start = position_from_sfen("lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1").unwrap();
+        let in_check = position_from_sfen("+R+B+P+P+P+P+P+P1/4K1+P+P1/4g4/9/9/9/9/9/4k4 b RB3P 1").unwrap();
+        let nine_pieces = position_from_sfen("+R+B+P+P+P+P+P+P1/4K1+P2/9/9/9/9/9/9/4k4 b RB3P 1").unwrap();
+
+        for rule in ImpasseRule::ALL {
+            assert_eq!(declaration(&start, rule), Declaration::Invalid("the king has not entered the enemy camp"));
+            assert_eq!(declaration(&in_check, rule), Declaration::Invalid("the king is in check"));
+            assert_eq!(declaration(&nine_pieces, rule), Declaration::Invalid("fewer than 10 pieces are in the enemy camp"));
+        }
+    }
+}
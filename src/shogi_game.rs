@@ -1,31 +1,98 @@
 use eframe::egui::{CentralPanel, Context, Rect, Vec2, Pos2};
 use shogi::{Position, Square, Move};
-use std::process::ChildStdin;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
-use std::io::Write;
+use std::time::Instant;
 
 use crate::Board;
+use crate::{Engine, EngineState};
+use crate::UsiOptionType;
+use crate::Config;
+use crate::GameRecord;
+use crate::record::{GameEnd, Glyph, STARTPOS_SFEN};
+use crate::kif;
+use crate::csa;
+use crate::jkf;
+use crate::notation::{self, NotationStyle};
+use crate::rules::{self, Declaration, ImpasseRule, Promotion, Repetition};
+use crate::usi::{BestMove, Score};
 use crate::Joystick;
 use crate::PieceButton;
 use crate::PIECE_TYPES;
 
+#[derive(Clone, Copy, PartialEq)]
+enum GameMode {
+    HumanVsHuman,
+    EngineVsPlayer(shogi::Color), // Color played by the engine
+    EngineVsEngine(shogi::Color), // Color played by the first engine, the second engine plays the other side
+}
+
+const ANIMATION_SECS: f32 = 0.3;
+
+// Browsing the moves of the game without changing it, the cursor is restored when leaving replay mode
+struct Replay {
+    game_path: Vec<(usize, usize)>, // Line and cursor to return to
+    game_cursor: usize,
+    labels: Vec<String>, // Text of each move for the move list in the selected notation
+    branches: Vec<(usize, Vec<String>)>, // Alternatives at each ply with more than one move, and the one on the line
+    autoplay: bool,
+    last_step: Instant,
+}
+
+// How the game on the board ended, moves are locked until New game or Undo
+struct GameResult {
+    end: GameEnd,
+    winner: Option<shogi::Color>, // None for draws and interrupted games
+    reason: String,
+}
+
+impl GameResult {
+    fn headline(&self) -> String {
+        match (self.winner, self.end) {
+            (Some(color), _)           => format!("{} wins", color),
+            (None, GameEnd::Interrupt) => String::from("Game interrupted"),
+            (None, _)                  => String::from("Draw"),
+        }
+    }
+}
+
 pub struct ShogiGame<'a> {
+    record: GameRecord, // Source of truth for the game, pos is rebuilt from it
+    record_path: String, // File for loading and saving the game record
+    move_input: String, // Move typed in the game panel
+    notation: NotationStyle,
+    move_clock: Instant, // Start of the current move, for the time column of the record
     pos: Position,
     board: Board<'a>,
-    promotion_flag: bool,
     error_message: String,
-    engine_input: ChildStdin,
-    engine_rx: mpsc::Receiver<String>,
-    engine_ms: String, // Duration for engine calculation in ms
+    engines: [Option<Engine>; 2], // None when no engine is configured or it failed to start
+    engine_ms: [String; 2], // Duration for engine calculation in ms
+    config: Config,
+    show_engine_options: bool,
+    options_slot: usize, // Engine shown in the options window
+    show_engine_settings: bool,
+    browse: Option<(usize, PathBuf)>, // Engine slot and directory shown by the executable browser
+    show_analysis: bool,
+    pv_cache: Vec<(NotationStyle, String, Vec<String>, String)>, // (notation, search_sfen, pv, text) of the shown analysis lines
+    analyze: bool, // Infinite analysis of the current position
+    eval: Option<Score>, // Last engine score from black's point of view, kept when the engine is idle
+    mode: GameMode,
+    paused: bool, // Engine vs engine: do not start new searches
+    step: bool,   // Engine vs engine: play one move while paused
+    max_moves: u32, // Engine vs engine: draw after this many plies
+    impasse_rule: ImpasseRule, // Judges entering king declarations
+    eve_engine1_color: shogi::Color, // Color for the first engine in the next engine vs engine game
+    game_over: Option<GameResult>, // Engines stop playing and the board is locked
+    animation: Option<(Move, Instant)>, // Last engine move, slid from its origin for ANIMATION_SECS
+    replay: Option<Replay>,
+    replay_ms: u32, // Autoplay delay between moves
     joystick_rx: mpsc::Receiver<(i32, i32, i32)>,
     joystick_state: (i32, i32, i32), // (switch, rank, file)
 }
 
 impl<'a> ShogiGame<'a> {
-    pub fn new(_ctx: &Context, pos: Position, board: Board<'a>, mut engine_input: ChildStdin, engine_rx: mpsc::Receiver<String>) -> Self {
-        writeln!(engine_input, "isready").expect("Failed to start engine"); // Start engine
-
+    pub fn new(_ctx: &Context, pos: Position, board: Board<'a>, config: Config) -> Self {
         // Start reading joystick
         let (joystick_tx, joystick_rx) = mpsc::channel();
         let mut joystick = Joystick::new();
@@ -33,17 +100,70 @@ impl<'a> ShogiGame<'a> {
             joystick.init(joystick_tx);
         });
 
-        Self { 
+        let record = GameRecord::from_sfen(&pos.to_sfen()).unwrap_or_else(|_| GameRecord::new(STARTPOS_SFEN));
+
+        let mut game = Self { 
+            record,
+            record_path: String::from("game.kifu"),
+            move_input: String::new(),
+            notation: NotationStyle::Japanese,
+            move_clock: Instant::now(),
             pos, 
             board, 
-            promotion_flag: false,
             error_message: String::new(), 
-            engine_input, 
-            engine_rx, 
-            engine_ms: String::from("3000"),
+            engines: [None, None], 
+            engine_ms: [String::from("3000"), String::from("3000")],
+            config,
+            show_engine_options: false,
+            options_slot: 0,
+            show_engine_settings: false,
+            browse: None,
+            show_analysis: false,
+            pv_cache: Vec::new(),
+            analyze: false,
+            eval: None,
+            mode: GameMode::HumanVsHuman,
+            paused: false,
+            step: false,
+            max_moves: 256,
+            impasse_rule: ImpasseRule::Points27,
+            eve_engine1_color: shogi::Color::Black,
+            game_over: None,
+            animation: None,
+            replay: None,
+            replay_ms: 1000,
             joystick_rx,
             joystick_state: (-1, -1, -1),
+        };
+        game.start_engine(0);
+        game.start_engine(1);
+        game
+    }
+
+    // (Re)start the configured engine in slot 0 or 1, falling back to no engine mode if it cannot be launched
+    fn start_engine(&mut self, slot: usize) {
+        self.engines[slot] = None;
+        let config = &self.config.engines[slot];
+        if !config.is_configured() {
+            return;
         }
+
+        match Engine::spawn(config) {
+            Ok(engine) => self.engines[slot] = Some(engine),
+            Err(err) => {
+                self.error_message = format!("Failed to start engine {}: {}", config.path, err);
+            }
+        }
+    }
+
+    // Any engine searching for a move to play
+    fn engine_thinking(&self) -> bool {
+        self.engines.iter().flatten().any(|engine| engine.is_thinking())
+    }
+
+    // Engine buttons are only enabled once the engine has answered readyok and is not searching
+    fn engine_idle(&self, slot: usize) -> bool {
+        self.engines[slot].as_ref().is_some_and(|engine| engine.is_ready() && !engine.is_thinking())
     }
 
     // Handle normal and drop moves, called from render_pieces when an ImageButton is clicked
@@ -51,37 +171,58 @@ impl<'a> ShogiGame<'a> {
         let active      = self.board.active;
         let active_hand = self.board.active_hand;
 
+        // Board is locked while the engine is searching, on the engine's turn, during replay and once the game is over
+        if self.engine_thinking() || self.is_engine_turn() || self.replay.is_some() || self.game_over.is_some() {
+            return;
+        }
+
+        // A pending promotion is settled by the next click or joystick press:
+        // the destination promotes, the square next to it does not and anywhere else cancels the move
+        if let Some((promote, keep)) = self.board.promotion {
+            let (promote_sq, keep_sq) = self.promotion_squares(promote);
+            let sq = Square::new(file as u8, rank as u8).unwrap();
+            self.board.reset_activity();
+            if sq == promote_sq {
+                self.play_move(promote);
+            }
+            else if sq == keep_sq {
+                self.play_move(keep);
+            }
+            return;
+        }
+
         // Attempt normal move with active piece
         if active != [-1, -1] {
             let active_piece = &self.board.piece_buttons[active[0] as usize][active[1] as usize];
 
-            if active_piece.piece != None && 
-                (curr_piece.piece == None || 
-                (curr_piece.piece != None && curr_piece.piece.unwrap().color != active_piece.piece.unwrap().color)) {
+            // Change selection of ally piece (active piece is same color as curr piece but different location)
+            let reselect = active_piece.piece.is_some() && curr_piece.piece.is_some() && curr_piece.piece.unwrap().color == active_piece.piece.unwrap().color && active != [rank as i32, file as i32];
+
+            // Active piece moving to an empty square or capturing
+            let moving = active_piece.piece.filter(|p| curr_piece.piece.is_none_or(|c| c.color != p.color));
+            if let Some(piece) = moving {
 
                 // FILE ORDER IS REVERSED, GOES FROM 9 to 1, rank a-i
                 // Square::new(file, rank)
-
                 let from_sq = Square::new(active[1] as u8, active[0] as u8).unwrap();
                 let to_sq = Square::new(file as u8, rank as u8).unwrap();
 
-                // Force promotion for now for manual moves
-                let m = if self.promotion_flag && !active_piece.is_promoted() && ((rank < 3 && self.pos.side_to_move() == shogi::Color::Black) || (rank > 5 && self.pos.side_to_move() == shogi::Color::White)) {
-                    Move::Normal{from: from_sq, to: to_sq, promote: true}
+                // Only targets of the legal moves are played, an optional promotion keeps the selection and asks for the choice
+                let legal = |promote: bool| self.board.legal_moves_to(to_sq).into_iter().find(|m| matches!(*m, Move::Normal { promote: p, .. } if p == promote));
+                let (promote, keep) = (legal(true), legal(false));
+                match (rules::promotion(piece, from_sq, to_sq), promote, keep) {
+                    (Promotion::Optional, Some(promote), Some(keep)) => {
+                        self.board.promotion = Some((promote, keep));
+                        return;
+                    }
+                    (Promotion::Forced, Some(m), _) | (Promotion::Never, _, Some(m)) => {
+                        self.play_move(m);
+                    }
+                    _ => (),
                 }
-                else {
-                    Move::Normal{from: from_sq, to: to_sq, promote: false}
-                };
-
-                self.error_message = format!("{}", m);
-                self.pos.make_move(m).unwrap_or_else(|err| {
-                    self.error_message = format!("Error in make_move: {}", err);
-                    Default::default()
-                });  
             }
 
-            // Change selection of ally piece (active piece is same color as curr piece but different location)
-            if active_piece.piece != None && curr_piece.piece != None && curr_piece.piece.unwrap().color == active_piece.piece.unwrap().color && active != [rank as i32, file as i32] {
+            if reselect {
                 self.board.reset_activity();
                 self.board.set_active(rank as i32, file as i32);
                 let sq = Square::new(file as u8, rank as u8).unwrap();
@@ -93,7 +234,7 @@ impl<'a> ShogiGame<'a> {
             }
         }
         // Clicked on side-to-move piece from inactive state
-        else if curr_piece.piece != None && curr_piece.piece.unwrap().color == self.pos.side_to_move() {
+        else if curr_piece.piece.is_some() && curr_piece.piece.unwrap().color == self.pos.side_to_move() {
             self.board.reset_activity();
             self.board.set_active(rank as i32, file as i32);
             let sq = Square::new(file as u8, rank as u8).unwrap();
@@ -104,18 +245,27 @@ impl<'a> ShogiGame<'a> {
         else if active_hand != usize::MAX {
             if (self.pos.side_to_move() == shogi::Color::Black && active_hand >= 7) || (self.pos.side_to_move() == shogi::Color::White && active_hand < 7) {
                 let to_sq = Square::new(file as u8, rank as u8).unwrap();
-                let m = Move::Drop{to: to_sq, piece_type: PIECE_TYPES[active_hand].piece_type};
-
-                self.error_message = format!("{}", m);
-                self.pos.make_move(m).unwrap_or_else(|err| {
-                    self.error_message = format!("Error in make_move: {}", err);
-                    Default::default()
-                });  
+                if let [m] = self.board.legal_moves_to(to_sq)[..] {
+                    self.play_move(m);
+                }
             }
             self.board.reset_activity();         
         }
     }
 
+    // Squares of the promotion choice for a promoting move: the destination and the square next to it towards the mover's side,
+    // or away from it when a piece leaving the promotion zone lands on the last rank
+    fn promotion_squares(&self, m: Move) -> (Square, Square) {
+        let to = rules::destination(m);
+        let rank = match (self.pos.side_to_move(), to.rank()) {
+            (shogi::Color::Black, 8) => 7,
+            (shogi::Color::Black, r) => r + 1,
+            (shogi::Color::White, 0) => 1,
+            (shogi::Color::White, r) => r - 1,
+        };
+        (to, Square::new(to.file(), rank).unwrap())
+    }
+
     // Renders grid lines, promotion zone circles, and possible active moves
     fn render_grid(&mut self, ui: &mut egui::Ui) {
         let position_factor = 62.22;              // Multiplied by rank and file to get position (560 / 9 = 62.22)
@@ -166,6 +316,24 @@ impl<'a> ShogiGame<'a> {
         painter.circle(Pos2::new(3.0 * position_factor + offset_x, 6.0 * position_factor + offset_y), radius, fill, stroke);
         painter.circle(Pos2::new(6.0 * position_factor + offset_x, 6.0 * position_factor + offset_y), radius, fill, stroke);
         
+        // Render evaluation bar left of the board, black's share grows from the bottom
+        if let Some(score) = self.eval {
+            let bar = Rect::from_min_max(Pos2::new(offset_x - 16.0, offset_y), Pos2::new(offset_x - 6.0, offset_y + board_size));
+            let split = offset_y + board_size * (1.0 - score.win_rate());
+
+            painter.rect_filled(bar, 0.0, egui::Color32::WHITE);
+            painter.rect_filled(Rect::from_min_max(Pos2::new(bar.min.x, split), bar.max), 0.0, egui::Color32::from_gray(30));
+            painter.rect_stroke(bar, 0.0, stroke);
+            painter.line_segment([Pos2::new(bar.min.x - 2.0, bar.center().y), Pos2::new(bar.max.x + 2.0, bar.center().y)], egui::Stroke::new(1.0, egui::Color32::RED));
+            painter.text(
+                Pos2::new(bar.center().x, offset_y + board_size + 10.0),
+                egui::Align2::CENTER_CENTER,
+                score.to_string(),
+                egui::FontId::proportional(11.0),
+                egui::Color32::GRAY,
+            );
+        }
+
         // Render possible active moves 
         for rank in 0..9 {
             for file in 0..9 {
@@ -208,7 +376,30 @@ impl<'a> ShogiGame<'a> {
                     Pos2::new(board_size - ((file + 1) as f32 * position_factor) + offset_x, rank as f32 * position_factor + offset_y), 
                     Vec2::new(60.0, 60.0)
                 );
-                let rect = Rect::from_min_size(min, size);
+                let mut rect = Rect::from_min_size(min, size);
+
+                // Slide the last engine move's piece from where it came from
+                if let Some((m, start)) = self.animation {
+                    let t = start.elapsed().as_secs_f32() / ANIMATION_SECS;
+                    if t < 1.0 && rules::destination(m) == Square::new(file as u8, rank as u8).unwrap() {
+                        let from = match m {
+                            Move::Normal { from, .. } => Pos2::new(
+                                board_size - ((from.file() + 1) as f32 * position_factor) + offset_x,
+                                from.rank() as f32 * position_factor + offset_y,
+                            ),
+                            // Drops come from the hand, black's on the right and white's on the left
+                            Move::Drop { piece_type, .. } => {
+                                let i = PIECE_TYPES.iter().position(|p| p.piece_type == piece_type).unwrap_or(0);
+                                match self.pos.side_to_move() {
+                                    shogi::Color::White => Pos2::new(board_size + offset_x + 25.0, board_size - 10.0 - (i as f32 * position_factor)),
+                                    shogi::Color::Black => Pos2::new(25.0, offset_y - 1.0 + i as f32 * position_factor),
+                                }
+                            }
+                        };
+                        let ease = 1.0 - (1.0 - t) * (1.0 - t);
+                        rect = Rect::from_min_size(from + (min - from) * ease, size);
+                    }
+                }
                
                 // Marks active square
                 if self.board.active == [rank as i32, file as i32] {
@@ -223,9 +414,35 @@ impl<'a> ShogiGame<'a> {
             }
         }
     
+        // Promotion choice over the destination square, the promoted piece (成) on it and the unpromoted piece (不成) next to it.
+        // Clicks go through handle_piece_move like clicks on the squares below, so the board lock applies.
+        if let Some((promote, _)) = self.board.promotion {
+            let (promote_sq, keep_sq) = self.promotion_squares(promote);
+            let piece = match promote {
+                Move::Normal { from, .. } => *self.pos.piece_at(from),
+                Move::Drop { .. }         => None,
+            };
+            if let Some(piece) = piece {
+                let choices = [
+                    (promote_sq, piece.promote().unwrap_or(piece), "Promote (成)"),
+                    (keep_sq, piece, "Do not promote (不成)"),
+                ];
+                for (sq, p, label) in choices {
+                    let rect = Rect::from_min_size(
+                        Pos2::new(board_size - ((sq.file() + 1) as f32 * position_factor) + offset_x, sq.rank() as f32 * position_factor + offset_y),
+                        Vec2::new(60.0, 60.0),
+                    );
+                    ui.painter().rect(rect, 4.0, egui::Color32::from_gray(235), egui::Stroke::new(2.0, egui::Color32::from_rgb(60, 110, 40)));
+                    if ui.put(rect, PieceButton::new_piece(p).button).on_hover_text(label).clicked() {
+                        let (rank, file) = (sq.rank() as usize, sq.file() as usize);
+                        self.handle_piece_move(rank, file, self.board.piece_buttons[rank][file].clone());
+                    }
+                }
+            }
+        }
+    
         // Render pieces in hand
-        for i in 0..14 {
-            let p = PIECE_TYPES[i];
+        for (i, &p) in PIECE_TYPES.iter().enumerate() {
             let pb = PieceButton::new_piece(p);
             let count = self.pos.hand(p);
     
@@ -243,7 +460,7 @@ impl<'a> ShogiGame<'a> {
                 if self.board.active_hand == i {
                     ui.painter().rect(rect, 0.0, fill, stroke);
                 }
-                if ui.put(rect, pb.button).clicked() && p.color == self.pos.side_to_move() {
+                if ui.put(rect, pb.button).clicked() && p.color == self.pos.side_to_move() && !self.engine_thinking() && !self.is_engine_turn() {
                     let tmp = self.board.active_hand; // Deselect hand piece on reclick
                     self.board.reset_activity();
                     if tmp != i {
@@ -273,9 +490,13 @@ impl<'a> ShogiGame<'a> {
         }
     }
 
-    // Apery engine communication
-    fn make_engine_move(&mut self) {
-        if let Ok(parsed) = self.engine_ms.parse::<i32>() {
+    // Start a background engine search for the side to move, the result is picked up in poll_engine
+    fn make_engine_move(&mut self, slot: usize) {
+        let Some(engine) = self.engines[slot].as_mut() else {
+            return;
+        };
+
+        if let Ok(parsed) = self.engine_ms[slot].parse::<i32>() {
             if parsed <= 0 {
                 self.error_message = String::from("Engine calculation time must be positive.");
                 return;
@@ -284,119 +505,1175 @@ impl<'a> ShogiGame<'a> {
                 self.error_message = String::from("Engine calculation time must be less than 10000 ms.");
                 return;
             }
+            self.board.reset_activity();
+            engine.go(&self.record.sfen(), parsed);
         } 
         else {
             self.error_message = String::from("Engine calculation time must be an integer.");
+        }
+    }
+
+    // Called every frame, plays an engine's bestmove once its search has finished
+    fn poll_engine(&mut self) {
+        for slot in 0..2 {
+            let Some(engine) = self.engines[slot].as_mut() else {
+                continue;
+            };
+            let alive = !engine.is_terminated();
+            let best_move = engine.poll();
+            if alive && engine.is_terminated() {
+                eprintln!("Engine {} terminated", engine.name);
+                self.error_message = format!("Engine {} terminated", engine.name);
+                self.mode = GameMode::HumanVsHuman;
+                self.analyze = false;
+                continue;
+            }
+            // An answer arriving after the game ended, e.g. by the other side resigning, is not played
+            let Some(best_move) = best_move.filter(|_| self.game_over.is_none()) else {
+                continue;
+            };
+            let color = self.pos.side_to_move();
+            let name = self.engines[slot].as_ref().map(|engine| engine.name.clone()).unwrap_or_default();
+
+            match best_move {
+                BestMove::Move(m) => {
+                    if self.play_move(m) {
+                        self.animation = Some((m, Instant::now()));
+                    }
+                    else {
+                        eprintln!("Illegal bestmove from {}: {} in {}", name, m, self.record.sfen());
+                        self.error_message = format!("{} played an illegal move: {}", name, m);
+                        // Asking again would get the same answer
+                        self.mode = GameMode::HumanVsHuman;
+                    }
+                }
+                BestMove::Resign => self.end_game(GameEnd::Resign, format!("{} resigns", color)),
+                BestMove::Win    => self.declare_win(),
+                BestMove::NoMove => self.end_game(GameEnd::Resign, format!("{} has no move and resigns", color)),
+                BestMove::Invalid(line) => {
+                    self.error_message = format!("{} sent an unreadable reply: {}", name, line);
+                    self.mode = GameMode::HumanVsHuman;
+                }
+            }
+
+            self.board.reset_activity();
+        }
+    }
+
+    // Make a move on the board, add it to the record and detect the end of the game. Returns false if the move was rejected.
+    fn play_move(&mut self, m: Move) -> bool {
+        let mover = self.pos.side_to_move();
+        let text = self.notation.format(&self.pos, m, self.record.last_move().map(rules::destination));
+        if let Err(err) = rules::make_move(&mut self.pos, m) {
+            self.error_message = format!("Error in make_move: {}", err);
+            return false;
+        }
+        self.record.play(m, Some(self.move_clock.elapsed().as_secs() as u32));
+        self.error_message = text;
+        self.move_clock = Instant::now();
+        // The side to move wins when the mover gave perpetual check
+        match rules::detect_repetition(&self.record.sfen()) {
+            Some(Repetition::Draw) => self.end_game(GameEnd::Sennichite, String::from("Sennichite, fourfold repetition")),
+            Some(Repetition::PerpetualCheck(color)) => {
+                let end = if color == mover { GameEnd::IllegalWin } else { GameEnd::IllegalLoss };
+                self.end_game(end, format!("Perpetual check by {}", color));
+            }
+            None => (),
+        }
+        self.check_game_over();
+        true
+    }
+
+    // Stop the game and write how it ended after the last move of the line.
+    // Searches still running would otherwise play a move after the result.
+    fn end_game(&mut self, end: GameEnd, reason: String) {
+        for engine in self.engines.iter_mut().flatten() {
+            engine.cancel();
+        }
+        self.board.reset_activity();
+        self.record.end_at_cursor(end);
+        self.set_result(end, reason);
+    }
+
+    fn set_result(&mut self, end: GameEnd, reason: String) {
+        let side = rules::sfen_side_to_move(&self.record.sfen());
+        let winner = end.side_to_move_wins().map(|wins| if wins { side } else { side.flip() });
+        self.game_over = Some(GameResult { end, winner, reason });
+    }
+
+    // Show the result recorded after the last move of the line once the board reaches it
+    fn restore_result(&mut self) {
+        if let Some(end) = self.record.line_end().filter(|_| !self.record.can_redo()) {
+            self.set_result(end, format!("Game record ends with {}", end.kif_name()));
+        }
+    }
+
+    // Rebuild the board after the record's cursor has moved
+    fn sync_position(&mut self) {
+        match self.record.position() {
+            Ok(pos) => self.pos = pos,
+            Err(err) => self.error_message = format!("Invalid game record {}: {}", self.record.sfen(), err),
+        }
+        self.board.reset_activity();
+        self.animation = None;
+        self.move_clock = Instant::now();
+    }
+
+    fn check_game_over(&mut self) {
+        if self.game_over.is_some() {
             return;
         }
+        if !rules::has_legal_move(&self.pos) {
+            self.end_game(GameEnd::Checkmate, format!("{} is checkmated", self.pos.side_to_move()));
+        }
+        else if matches!(self.mode, GameMode::EngineVsEngine(_)) && self.record.cursor >= self.max_moves as usize {
+            self.end_game(GameEnd::MaxMoves, format!("Move limit of {} reached", self.max_moves));
+        }
+    }
 
-        writeln!(self.engine_input, "position sfen {}", self.pos.to_sfen()).expect("Failed to set board position");
-        writeln!(self.engine_input, "go byoyomi {}", self.engine_ms).expect("Failed to find best move");
+    // Options window built from the engine's "option" lines, changes are sent with setoption on Apply
+    fn render_engine_options(&mut self, ctx: &Context) {
+        let mut slot = self.options_slot;
+        if self.engines[slot].is_none() {
+            slot = 1 - slot;
+        }
+        let other_loaded = self.engines[1 - slot].is_some();
+        let Some(engine) = self.engines[slot].as_mut() else {
+            self.show_engine_options = false;
+            return;
+        };
+        let mut open = self.show_engine_options;
+        let mut apply = false;
+        let mut pressed = None;
 
-        while let Ok(line) = self.engine_rx.recv() {
-            if line.starts_with("bestmove") {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                let best_move = parts[1].to_string();
-                self.error_message = format!("{}", best_move);
+        egui::Window::new("Engine options").open(&mut open).show(ctx, |ui| {
+            if other_loaded {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.options_slot, 0, "Engine 1");
+                    ui.selectable_value(&mut self.options_slot, 1, "Engine 2");
+                });
+            }
+            ui.label(format!("{} by {}", engine.name, engine.author));
+            ui.separator();
 
-                let m = Move::from_sfen(&best_move).unwrap();
-                self.pos.make_move(m).unwrap_or_else(|err| {
-                    self.error_message = format!("Error in make_move: {}", err);
-                    Default::default()
+            egui::ScrollArea::vertical().max_height(400.0).show(ui, |ui| {
+                egui::Grid::new("engine_options").num_columns(2).striped(true).show(ui, |ui| {
+                    for option in engine.options.iter_mut() {
+                        ui.label(&option.name);
+                        match &option.kind {
+                            UsiOptionType::Check => {
+                                let mut checked = option.value == "true";
+                                if ui.checkbox(&mut checked, "").changed() {
+                                    option.value = checked.to_string();
+                                }
+                            }
+                            UsiOptionType::Spin { min, max } => {
+                                let mut value = option.value.parse::<i64>().unwrap_or(*min);
+                                if ui.add(egui::DragValue::new(&mut value).range(*min..=*max)).changed() {
+                                    option.value = value.to_string();
+                                }
+                            }
+                            UsiOptionType::Combo { vars } => {
+                                egui::ComboBox::from_id_salt(&option.name)
+                                    .selected_text(option.value.clone())
+                                    .show_ui(ui, |ui| {
+                                        for var in vars {
+                                            ui.selectable_value(&mut option.value, var.clone(), var);
+                                        }
+                                    });
+                            }
+                            UsiOptionType::Button => {
+                                if ui.button("Press").clicked() {
+                                    pressed = Some(option.name.clone());
+                                }
+                            }
+                            UsiOptionType::String | UsiOptionType::Filename => {
+                                ui.text_edit_singleline(&mut option.value);
+                            }
+                        }
+                        ui.end_row();
+                    }
                 });
+            });
 
-                self.board.reset_activity();
-                break;
+            ui.separator();
+            ui.horizontal(|ui| {
+                let idle = engine.is_ready() && !engine.is_thinking();
+                if ui.add_enabled(idle, egui::Button::new("Apply")).clicked() {
+                    apply = true;
+                }
+                if ui.button("Reset to defaults").clicked() {
+                    for option in engine.options.iter_mut() {
+                        option.value = option.default.clone();
+                    }
+                }
+            });
+        });
+
+        if let Some(name) = pressed {
+            engine.press_button(&name);
+        }
+        if apply {
+            engine.apply_options();
+        }
+        self.show_engine_options = open;
+    }
+
+    // Engine executable picker, also used to switch engines or unload them.
+    // The second engine is only needed for engine vs engine games.
+    fn render_engine_settings(&mut self, ctx: &Context) {
+        let mut open = self.show_engine_settings;
+        let mut start = [false; 2];
+        let mut unload = [false; 2];
+        let mut browse = None;
+        let mut save = false;
+
+        egui::Window::new("Engine settings").open(&mut open).show(ctx, |ui| {
+            for slot in 0..2 {
+                let config = &mut self.config.engines[slot];
+                ui.strong(if slot == 0 { "Engine 1" } else { "Engine 2 (engine vs engine opponent)" });
+                egui::Grid::new(("engine_settings", slot)).num_columns(2).show(ui, |ui| {
+                    ui.label("Executable:");
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut config.path);
+                        if ui.button("Browse...").clicked() {
+                            browse = Some(slot);
+                        }
+                    });
+                    ui.end_row();
+                    ui.label("Working directory:");
+                    ui.text_edit_singleline(&mut config.working_dir);
+                    ui.end_row();
+                    ui.label("Arguments:");
+                    ui.text_edit_singleline(&mut config.args);
+                    ui.end_row();
+                });
+                ui.horizontal(|ui| {
+                    start[slot]  = ui.button("Start engine").clicked();
+                    unload[slot] = ui.add_enabled(self.engines[slot].is_some(), egui::Button::new("Unload engine")).clicked();
+                });
+                ui.separator();
+            }
+            save = ui.button(format!("Save to {}", self.config.path.display())).clicked();
+        });
+
+        for slot in 0..2 {
+            if start[slot] {
+                self.error_message.clear();
+                self.start_engine(slot);
+            }
+            if unload[slot] {
+                self.engines[slot] = None;
+            }
+        }
+        if save {
+            if let Err(err) = self.config.save() {
+                self.error_message = format!("Failed to save {}: {}", self.config.path.display(), err);
+            }
+        }
+        // Start from the directory of the current executable
+        if let Some(slot) = browse {
+            let config = &self.config.engines[slot];
+            let path = PathBuf::from(config.working_dir.trim()).join(config.path.trim());
+            let dir = path.parent().filter(|dir| dir.is_dir()).map(PathBuf::from).or(std::env::current_dir().ok()).unwrap_or_default();
+            self.browse = Some((slot, dir.canonicalize().unwrap_or(dir)));
+        }
+        if self.browse.is_some() {
+            self.render_engine_browser(ctx);
+        }
+        self.show_engine_settings = open;
+    }
+
+    // Directory browser for the engine executable. Picking a file sets the path and runs the engine from its directory,
+    // where engines usually look for their evaluation files.
+    fn render_engine_browser(&mut self, ctx: &Context) {
+        let Some((slot, dir)) = self.browse.clone() else {
+            return;
+        };
+        let mut open = true;
+        let mut next_dir = None;
+        let mut picked = None;
+
+        egui::Window::new("Choose engine executable").open(&mut open).default_width(420.0).show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                if ui.add_enabled(dir.parent().is_some(), egui::Button::new("Up")).clicked() {
+                    next_dir = dir.parent().map(PathBuf::from);
+                }
+                ui.label(dir.display().to_string());
+            });
+            ui.separator();
+
+            let mut entries: Vec<(bool, String, PathBuf)> = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries.flatten().map(|entry| {
+                    let path = entry.path();
+                    (!path.is_dir(), entry.file_name().to_string_lossy().into_owned(), path)
+                }).collect(),
+                Err(err) => {
+                    ui.label(format!("Cannot read directory: {}", err));
+                    Vec::new()
+                }
+            };
+            // Directories first, then files, by name
+            entries.sort();
+
+            egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                for (is_file, name, path) in entries {
+                    if !is_file && ui.selectable_label(false, format!("{}/", name)).clicked() {
+                        next_dir = Some(path);
+                    }
+                    else if is_file && ui.selectable_label(false, name).clicked() {
+                        picked = Some(path);
+                    }
+                }
+            });
+        });
+
+        if let Some(path) = picked {
+            let config = &mut self.config.engines[slot];
+            config.path = path.display().to_string();
+            config.working_dir = dir.display().to_string();
+            self.browse = None;
+        }
+        else if let Some(next_dir) = next_dir {
+            self.browse = Some((slot, next_dir));
+        }
+        else if !open {
+            self.browse = None;
+        }
+    }
+
+    // PV text of each analysis line. Formatting replays the game from the SFEN, so a line is only formatted
+    // when its PV, the searched position or the notation changed since the last frame
+    fn analysis_pv_texts(&mut self) -> Vec<String> {
+        let Some(engine) = self.engines[0].as_ref() else {
+            return Vec::new();
+        };
+
+        let notation = self.notation;
+        let cache = engine.lines.iter().map(|line| {
+            let cached = self.pv_cache.iter().find(|(style, sfen, pv, _)| *style == notation && *sfen == engine.search_sfen && *pv == line.pv);
+            match cached {
+                Some(entry) => entry.clone(),
+                None => (notation, engine.search_sfen.clone(), line.pv.clone(), notation.pv_to_string(&engine.search_sfen, &line.pv)),
             }
+        }).collect();
+        self.pv_cache = cache;
+        self.pv_cache.iter().map(|(_, _, _, text)| text.clone()).collect()
+    }
+
+    fn render_analysis(&mut self, ctx: &Context) {
+        let mut open = self.show_analysis;
+        let mut multipv = None;
+        let pv_texts = self.analysis_pv_texts();
+
+        egui::Window::new("Analysis").open(&mut open).default_width(520.0).show(ctx, |ui| {
+            let Some(engine) = self.engines[0].as_ref() else {
+                ui.label("No engine loaded.");
+                return;
+            };
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.analyze, "Analyze");
+                match engine.option("MultiPV") {
+                    Some(option) => {
+                        let max = match option.kind {
+                            UsiOptionType::Spin { max, .. } => max.min(20),
+                            _ => 20,
+                        };
+                        let mut value = option.value.parse::<i64>().unwrap_or(1);
+                        ui.label("MultiPV:");
+                        let idle = engine.is_ready() && !engine.is_thinking();
+                        if ui.add_enabled(idle, egui::DragValue::new(&mut value).range(1..=max)).changed() {
+                            multipv = Some(value);
+                        }
+                    }
+                    None => {
+                        ui.label("Engine has no MultiPV option");
+                    }
+                }
+            });
+
+            let field = |value: Option<u64>| value.map(|v| v.to_string()).unwrap_or_default();
+            if let Some(best) = engine.best_line() {
+                ui.label(format!(
+                    "Depth {}  Nodes {}  NPS {}  Time {}",
+                    field(best.depth.map(u64::from)),
+                    field(best.nodes),
+                    field(best.nps),
+                    best.time_ms.map(|ms| format!("{:.1} s", ms as f32 / 1000.0)).unwrap_or_default(),
+                ));
+            }
+            ui.separator();
+
+            egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+                egui::Grid::new("analysis").num_columns(4).striped(true).show(ui, |ui| {
+                    ui.strong("#");
+                    ui.strong("Score");
+                    ui.strong("Depth");
+                    ui.strong("Principal variation");
+                    ui.end_row();
+
+                    for (i, line) in engine.lines.iter().enumerate() {
+                        ui.label((i + 1).to_string());
+                        ui.label(line.score.map(|score| score.to_string()).unwrap_or_default());
+                        ui.label(match (line.depth, line.seldepth) {
+                            (Some(depth), Some(seldepth)) => format!("{}/{}", depth, seldepth),
+                            (Some(depth), None) => depth.to_string(),
+                            _ => String::new(),
+                        });
+                        ui.add(egui::Label::new(pv_texts.get(i).cloned().unwrap_or_default()).wrap());
+                        ui.end_row();
+                    }
+                });
+            });
+        });
+
+        // The analysis is restarted by update_analysis once the engine is ready again
+        if let (Some(value), Some(engine)) = (multipv, self.engines[0].as_mut()) {
+            engine.set_option("MultiPV", &value.to_string());
         }
+        self.show_analysis = open;
     }
 
-    #[allow(dead_code)]
-    fn engine_vs_player(&mut self) {
-        // let engine play against you
-        todo!();
+    // Track the engine's score while it is searching, normalized from the side to move to black
+    fn update_eval(&mut self) {
+        let Some(engine) = self.engines.iter().flatten().find(|engine| engine.is_thinking() || engine.is_analyzing()) else {
+            return;
+        };
+
+        if let Some(score) = engine.best_line().and_then(|line| line.score) {
+            self.eval = Some(match rules::sfen_side_to_move(&engine.search_sfen) {
+                shogi::Color::Black => score,
+                shogi::Color::White => score.negate(),
+            });
+        }
     }
 
-    #[allow(dead_code)]
-    fn engine_vs_engine(&mut self) {
-        // let engine play against itself
-        todo!();
+    // Keep an infinite search running on the current position while Analyze is on
+    fn update_analysis(&mut self) {
+        // Both engines are busy playing in engine vs engine games
+        if matches!(self.mode, GameMode::EngineVsEngine(_)) {
+            self.analyze = false;
+        }
+        let Some(engine) = self.engines[0].as_mut() else {
+            self.analyze = false;
+            return;
+        };
+
+        if !self.analyze {
+            if engine.is_analyzing() {
+                engine.cancel();
+            }
+            return;
+        }
+
+        if engine.is_ready() && !engine.is_thinking() {
+            let sfen = self.record.sfen();
+            if !engine.is_analyzing() || engine.search_sfen != sfen {
+                engine.go_infinite(&sfen);
+            }
+        }
     }
 
-    #[allow(dead_code)]
+    // Let the engine play engine_color, it moves on its own whenever that side is to move
+    fn engine_vs_player(&mut self, engine_color: Option<shogi::Color>) {
+        self.mode = match engine_color {
+            Some(color) => GameMode::EngineVsPlayer(color),
+            None => GameMode::HumanVsHuman,
+        };
+        self.board.reset_activity();
+    }
+
+    // Engine slot playing the side to move, None on a human's turn
+    fn engine_to_move(&self) -> Option<usize> {
+        let side = self.pos.side_to_move();
+        match self.mode {
+            GameMode::EngineVsPlayer(color) if color == side => Some(0),
+            GameMode::EngineVsEngine(color) => Some(if color == side { 0 } else { 1 }),
+            _ => None,
+        }
+    }
+
+    // Engine name or "Human" for the side played by color in the current mode
+    fn player_name(&self, color: shogi::Color) -> String {
+        let slot = match self.mode {
+            GameMode::EngineVsPlayer(engine_color) if engine_color == color => Some(0),
+            GameMode::EngineVsEngine(engine1_color) => Some(if engine1_color == color { 0 } else { 1 }),
+            _ => None,
+        };
+        slot.and_then(|slot| self.engines[slot].as_ref())
+            .map(|engine| engine.name.clone())
+            .unwrap_or(String::from("Human"))
+    }
+
+    fn is_engine_turn(&self) -> bool {
+        self.engine_to_move().is_some()
+    }
+
+    // Called every frame, starts the engine's reply once the human has moved
+    // and drives engine vs engine games unless paused.
+    fn update_game_mode(&mut self) {
+        let needed = match self.mode {
+            GameMode::HumanVsHuman      => 0,
+            GameMode::EngineVsPlayer(_) => 1,
+            GameMode::EngineVsEngine(_) => 2,
+        };
+        if self.engines[..needed].iter().any(|engine| engine.is_none()) {
+            self.mode = GameMode::HumanVsHuman;
+        }
+        if self.game_over.is_some() || self.engine_thinking() || self.replay.is_some() {
+            return;
+        }
+
+        let Some(slot) = self.engine_to_move() else {
+            return;
+        };
+        if matches!(self.mode, GameMode::EngineVsEngine(_)) && self.paused && !self.step {
+            return;
+        }
+        if self.engine_idle(slot) {
+            self.step = false;
+            self.make_engine_move(slot);
+        }
+    }
+
+    // Start a new game between the two configured engines
+    fn engine_vs_engine(&mut self, engine1_color: shogi::Color) {
+        self.new_game();
+        self.mode = GameMode::EngineVsEngine(engine1_color);
+        self.paused = false;
+        self.step = false;
+    }
+
+    // Enter replay mode at the current move, engines stop playing until the replay is closed
     fn replay_game(&mut self) {
-        // https://www.youtube.com/watch?v=6z8MzWRm__s&list=PLB3D925021814AD0A
-        // extract sfen strings of each state and replay
-        todo!();
+        for engine in self.engines.iter_mut().flatten() {
+            engine.cancel();
+        }
+        self.board.reset_activity();
+
+        self.replay = Some(Replay {
+            game_path: self.record.path.clone(),
+            game_cursor: self.record.cursor,
+            labels: Vec::new(),
+            branches: Vec::new(),
+            autoplay: false,
+            last_step: Instant::now(),
+        });
+        self.update_move_list();
+    }
+
+    // Rebuild the replay move list after the line or the notation changed
+    fn update_move_list(&mut self) {
+        let Some(replay) = self.replay.as_mut() else {
+            return;
+        };
+        let moves = self.record.usi_moves();
+        replay.labels = self.notation.format_moves(&self.record.start_sfen, &moves);
+        replay.branches = (0..moves.len()).map(|ply| {
+            let alternatives = self.record.alternatives(ply);
+            if alternatives.len() < 2 {
+                return (0, Vec::new());
+            }
+            let selected = alternatives.iter().position(|m| m.is_ok_and(|m| m.to_string() == moves[ply])).unwrap_or(0);
+            let labels = alternatives.iter().map(|alternative| match alternative {
+                Ok(m) => {
+                    let line: Vec<String> = moves[..ply].iter().cloned().chain([m.to_string()]).collect();
+                    self.notation.format_moves(&self.record.start_sfen, &line).pop().unwrap_or_default()
+                }
+                // A result recorded in place of the move
+                Err(end) => end.kif_name().to_string(),
+            }).collect();
+            (selected, labels)
+        }).collect();
+    }
+
+    // Edits to the variations during a replay move the game to the position on the board
+    fn edit_variations(&mut self, promote: bool) {
+        if promote {
+            self.record.promote_line();
+        }
+        else if !self.record.delete_variation() {
+            return;
+        }
+        self.sync_position();
+        self.update_move_list();
+        if let Some(replay) = self.replay.as_mut() {
+            replay.game_path = self.record.path.clone();
+            replay.game_cursor = self.record.cursor;
+        }
+    }
+
+    // Show the position after the first ply moves
+    fn replay_seek(&mut self, ply: usize) {
+        let Some(replay) = self.replay.as_mut() else {
+            return;
+        };
+        replay.last_step = Instant::now();
+        self.record.seek(ply);
+        self.sync_position();
+    }
+
+    // Leave replay mode and go back to the position the replay was started from
+    fn exit_replay(&mut self) {
+        let Some(replay) = self.replay.take() else {
+            return;
+        };
+        self.record.path = replay.game_path;
+        self.record.seek(replay.game_cursor);
+        self.sync_position();
+    }
+
+    // Called every frame, advances autoplay
+    fn update_replay(&mut self) {
+        let Some(replay) = self.replay.as_mut() else {
+            return;
+        };
+        if !replay.autoplay || replay.last_step.elapsed().as_millis() < self.replay_ms as u128 {
+            return;
+        }
+        if !self.record.can_redo() {
+            replay.autoplay = false;
+            return;
+        }
+        self.replay_seek(self.record.cursor + 1);
+    }
+
+    // Navigation buttons, autoplay and clickable move list
+    fn render_replay(&mut self, ui: &mut egui::Ui) {
+        let Some(replay) = self.replay.as_mut() else {
+            if ui.button("Replay game").clicked() {
+                self.replay_game();
+            }
+            return;
+        };
+
+        let (ply, last) = (self.record.cursor, self.record.line().len());
+        let mut seek = None;
+        let mut choose = None;
+        ui.horizontal(|ui| {
+            if ui.add_enabled(ply > 0, egui::Button::new("|<")).clicked() {
+                seek = Some(0);
+            }
+            if ui.add_enabled(ply > 0, egui::Button::new("<")).clicked() {
+                seek = Some(ply - 1);
+            }
+            if ui.add_enabled(ply < last, egui::Button::new(">")).clicked() {
+                seek = Some(ply + 1);
+            }
+            if ui.add_enabled(ply < last, egui::Button::new(">|")).clicked() {
+                seek = Some(last);
+            }
+            ui.label(format!("{} / {}", ply, last));
+        });
+        ui.horizontal(|ui| {
+            if ui.add_enabled(ply < last || replay.autoplay, egui::SelectableLabel::new(replay.autoplay, "Autoplay")).clicked() {
+                replay.autoplay = !replay.autoplay;
+                replay.last_step = Instant::now();
+            }
+            ui.add(egui::Slider::new(&mut self.replay_ms, 100..=5000).suffix(" ms"));
+        });
+
+        // Comments are shown under the move they follow
+        let comment_lines = |ui: &mut egui::Ui, comments: &[String]| {
+            for comment in comments {
+                ui.horizontal_wrapped(|ui| {
+                    ui.add_space(24.0);
+                    ui.weak(comment);
+                });
+            }
+        };
+        let line = self.record.line();
+        egui::ScrollArea::vertical().max_height(300.0).show(ui, |ui| {
+            if ui.selectable_label(ply == 0, "Start").clicked() {
+                seek = Some(0);
+            }
+            comment_lines(ui, &self.record.start_comments);
+            for (i, (label, (selected, alternatives))) in replay.labels.iter().zip(&replay.branches).enumerate() {
+                let glyph = line[i].glyph.map(Glyph::symbol).unwrap_or_default();
+                let marker = if alternatives.is_empty() { "" } else { " +" };
+                if ui.selectable_label(ply == i + 1, format!("{:>3} {}{}{}", i + 1, label, glyph, marker)).clicked() {
+                    seek = Some(i + 1);
+                }
+                comment_lines(ui, &line[i].comments);
+                // Other moves played at this ply, clicking one follows its line
+                if !alternatives.is_empty() {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.add_space(24.0);
+                        for (k, alternative) in alternatives.iter().enumerate() {
+                            if ui.selectable_label(k == *selected, alternative).clicked() && k != *selected {
+                                choose = Some((i, k));
+                            }
+                        }
+                    });
+                }
+            }
+        });
+
+        // Annotation of the move leading to the position on the board, at the start only a comment
+        let mut glyph = None;
+        ui.horizontal(|ui| {
+            ui.label("Glyph:");
+            let current = self.record.glyph();
+            for g in Glyph::ALL {
+                if ui.add_enabled(ply > 0, egui::SelectableLabel::new(current == Some(g), g.symbol())).clicked() {
+                    glyph = Some(if current == Some(g) { None } else { Some(g) });
+                }
+            }
+        });
+        let mut comment = self.record.comments().join("\n");
+        let comment_changed = ui.add(egui::TextEdit::multiline(&mut comment).hint_text("Comment").desired_rows(2)).changed();
+
+        let mut edit = None;
+        ui.horizontal(|ui| {
+            if ui.add_enabled(!self.record.path.is_empty(), egui::Button::new("Make main line")).clicked() {
+                edit = Some(true);
+            }
+            if ui.add_enabled(self.record.in_variation(), egui::Button::new("Delete variation")).clicked() {
+                edit = Some(false);
+            }
+        });
+
+        let close = ui.button("Back to game").clicked();
+        if let Some(glyph) = glyph {
+            self.record.set_glyph(glyph);
+        }
+        if comment_changed {
+            self.record.set_comments(if comment.is_empty() { Vec::new() } else { comment.split('\n').map(String::from).collect() });
+        }
+        if let Some(ply) = seek {
+            self.replay_seek(ply);
+        }
+        if let Some((ply, index)) = choose {
+            self.record.choose(ply, index);
+            self.replay_seek(self.record.cursor);
+            self.update_move_list();
+        }
+        if let Some(promote) = edit {
+            self.edit_variations(promote);
+        }
+        if close {
+            self.exit_replay();
+        }
+    }
+
+    // Play the move in move_input written in KI2 or USI notation
+    fn play_typed_move(&mut self) {
+        if self.engine_thinking() || self.is_engine_turn() || self.replay.is_some() || self.game_over.is_some() {
+            return;
+        }
+        let text = self.move_input.trim();
+        let last_to = self.record.last_move().map(rules::destination);
+        let Some(m) = notation::parse_ki2(&self.pos, text, last_to).or(rules::parse_move(text)) else {
+            self.error_message = format!("Cannot read move {}", text);
+            return;
+        };
+        if self.play_move(m) {
+            self.move_input.clear();
+        }
+        self.board.reset_activity();
+    }
+
+    // Game mode selection and engine vs engine controls
+    fn render_game_panel(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Game");
+        let engine_loaded = [self.engines[0].is_some(), self.engines[1].is_some()];
+
+        ui.label("Engine plays:");
+        ui.horizontal(|ui| {
+            let current = match self.mode {
+                GameMode::EngineVsPlayer(color) => Some(color),
+                _ => None,
+            };
+            for (label, color) in [("None", None), ("Black", Some(shogi::Color::Black)), ("White", Some(shogi::Color::White))] {
+                let enabled = color.is_none() || engine_loaded[0];
+                if ui.add_enabled(enabled, egui::SelectableLabel::new(current == color, label)).clicked() {
+                    self.engine_vs_player(color);
+                }
+            }
+        });
+
+        ui.horizontal(|ui| {
+            ui.label("Impasse:");
+            for rule in ImpasseRule::ALL {
+                if ui.selectable_label(self.impasse_rule == rule, rule.name()).clicked() {
+                    self.impasse_rule = rule;
+                }
+            }
+        });
+        let points = [shogi::Color::Black, shogi::Color::White].map(|color| rules::impasse_points(&self.pos, color).0);
+        ui.label(format!("Points: Black {}, White {}", points[0], points[1]));
+
+        ui.horizontal(|ui| {
+            ui.label("Notation:");
+            for style in NotationStyle::ALL {
+                if ui.selectable_label(self.notation == style, style.name()).clicked() && self.notation != style {
+                    self.notation = style;
+                    self.update_move_list();
+                }
+            }
+        });
+
+        // Typed moves, e.g. ７六歩, 同　銀, ５八金右 or 7g7f
+        ui.horizontal(|ui| {
+            ui.label("Move:");
+            let response = ui.add(egui::TextEdit::singleline(&mut self.move_input).desired_width(100.0));
+            let submit = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+            if (ui.button("Play").clicked() || submit) && !self.move_input.trim().is_empty() {
+                self.play_typed_move();
+            }
+        });
+
+        ui.separator();
+        ui.strong("Engine vs engine");
+        if !engine_loaded[0] || !engine_loaded[1] {
+            ui.label("Load two engines in Engine settings.");
+        }
+        egui::Grid::new("engine_vs_engine").num_columns(2).show(ui, |ui| {
+            ui.label("Engine 1 plays:");
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.eve_engine1_color, shogi::Color::Black, "Black");
+                ui.selectable_value(&mut self.eve_engine1_color, shogi::Color::White, "White");
+            });
+            ui.end_row();
+            ui.label("Engine 2 time:");
+            ui.horizontal(|ui| {
+                ui.add(egui::TextEdit::singleline(&mut self.engine_ms[1]).desired_width(40.0));
+                ui.label("ms");
+            });
+            ui.end_row();
+            ui.label("Move limit:");
+            ui.add(egui::DragValue::new(&mut self.max_moves).range(1..=1000));
+            ui.end_row();
+        });
+        ui.horizontal(|ui| {
+            if ui.add_enabled(engine_loaded[0] && engine_loaded[1], egui::Button::new("Start")).clicked() {
+                self.engine_vs_engine(self.eve_engine1_color);
+            }
+            let playing = matches!(self.mode, GameMode::EngineVsEngine(_)) && self.game_over.is_none();
+            if ui.add_enabled(playing, egui::Button::new(if self.paused { "Resume" } else { "Pause" })).clicked() {
+                self.paused = !self.paused;
+            }
+            if ui.add_enabled(playing && self.paused, egui::Button::new("Step")).clicked() {
+                self.step = true;
+            }
+        });
+
+        if let Some(result) = &self.game_over {
+            ui.separator();
+            ui.strong(format!("{}: {}", result.headline(), result.reason));
+        }
+
+        ui.separator();
+        ui.strong("Replay");
+        self.render_replay(ui);
+
+        ui.separator();
+        ui.strong("Game record");
+        ui.horizontal(|ui| {
+            ui.label("File:");
+            ui.text_edit_singleline(&mut self.record_path);
+        });
+        ui.horizontal(|ui| {
+            if ui.add_enabled(self.replay.is_none(), egui::Button::new("Load")).clicked() {
+                self.load_record();
+            }
+            if ui.button("Save").clicked() {
+                self.save_record();
+            }
+        });
+    }
+
+    // Lowercase extension of the record file, which picks the format
+    fn record_extension(&self) -> String {
+        std::path::Path::new(&self.record_path).extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    }
+
+    // Read a KIF, CSA or JKF file into the game record, the board shows its last position
+    fn load_record(&mut self) {
+        let text = match std::fs::read_to_string(&self.record_path) {
+            Ok(text) => text,
+            Err(err) => {
+                self.error_message = format!("Failed to read {}: {}", self.record_path, err);
+                return;
+            }
+        };
+        let parsed = match self.record_extension().as_str() {
+            "csa"          => csa::parse(&text),
+            "jkf" | "json" => jkf::parse(&text),
+            _              => kif::parse(&text),
+        };
+        match parsed {
+            Ok(record) => {
+                for engine in self.engines.iter_mut().flatten() {
+                    engine.new_game();
+                }
+                self.mode = GameMode::HumanVsHuman;
+                self.eval = None;
+                self.game_over = None;
+                self.record = record;
+                self.sync_position();
+                self.restore_result();
+                self.error_message = format!("Loaded {}", self.record_path);
+            }
+            Err(err) => self.error_message = format!("Failed to load {}: {}", self.record_path, err),
+        }
+    }
+
+    fn save_record(&mut self) {
+        // Games played here get the current players, loaded games keep theirs
+        for (key, color) in [("先手", shogi::Color::Black), ("後手", shogi::Color::White)] {
+            if self.record.header_value(key).is_none() {
+                let name = self.player_name(color);
+                self.record.set_header(key, name);
+            }
+        }
+        let text = match self.record_extension().as_str() {
+            "csa"          => csa::write(&self.record),
+            "jkf" | "json" => jkf::write(&self.record),
+            _              => kif::write(&self.record),
+        };
+        match std::fs::write(&self.record_path, text) {
+            Ok(()) => self.error_message = format!("Saved {}", self.record_path),
+            Err(err) => self.error_message = format!("Failed to save {}: {}", self.record_path, err),
+        }
     }
 
     // New game: reset board, position, and engine
     fn new_game(&mut self) {
+        self.replay = None;
+        for engine in self.engines.iter_mut().flatten() {
+            engine.new_game();
+        }
         self.board = Board::new();
-        self.pos = Position::new();
-        self.pos.set_sfen("lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1").unwrap();  
-        writeln!(self.engine_input, "position startpos").expect("Failed to reset board position");
+        self.record = GameRecord::new(STARTPOS_SFEN);
+        self.sync_position();
         self.error_message.clear();
+        self.eval = None;
+        self.game_over = None;
+        self.animation = None;
     }
 
-    // Undo move with shogi::Position MoveHistory
+    // Step back in the game record, undone moves can be redone until another move is played
     fn undo_move(&mut self) {
-        self.pos.unmake_move().unwrap();
-        writeln!(self.engine_input, "position sfen {}", self.pos.to_sfen()).expect("Failed to undo move");
+        if !self.record.can_undo() {
+            return;
+        }
+        self.stop_engines();
+        self.record.undo();
+        self.sync_position();
+        // Against the engine, take back its reply as well so the human is to move
+        if matches!(self.mode, GameMode::EngineVsPlayer(_)) && self.is_engine_turn() && self.record.undo() {
+            self.sync_position();
+        }
         self.error_message.clear();
     }
+
+    fn redo_move(&mut self) {
+        if !self.record.can_redo() {
+            return;
+        }
+        self.stop_engines();
+        self.record.redo();
+        self.sync_position();
+        // Redo the engine's reply too, mirroring undo_move
+        if matches!(self.mode, GameMode::EngineVsPlayer(_)) && self.is_engine_turn() && self.record.redo() {
+            self.sync_position();
+        }
+        self.restore_result();
+        self.check_game_over();
+        self.error_message.clear();
+    }
+
+    // The human to move gives up, only on their own turn so the record says who resigned
+    fn resign(&mut self) {
+        let color = self.pos.side_to_move();
+        self.end_game(GameEnd::Resign, format!("{} resigns", color));
+    }
+
+    // The side to move declares an entering king win, judged by the impasse rule. A declaration that does not meet it loses.
+    fn declare_win(&mut self) {
+        let color = self.pos.side_to_move();
+        let (points, _) = rules::impasse_points(&self.pos, color);
+        match rules::declaration(&self.pos, self.impasse_rule) {
+            Declaration::Win => self.end_game(GameEnd::DeclareWin, format!("{} declares an entering king win with {} points", color, points)),
+            Declaration::Draw => self.end_game(GameEnd::Jishogi, format!("{} declares an impasse with {} points, draw", color, points)),
+            Declaration::Invalid(reason) => self.end_game(GameEnd::IllegalLoss, format!("{} declared a win but {}", color, reason)),
+        }
+    }
+
+    // Result over the board with the ways to leave it
+    fn render_result_banner(&mut self, ctx: &Context) {
+        let Some(result) = &self.game_over else {
+            return;
+        };
+        let (headline, reason) = (result.headline(), result.reason.clone());
+        let (mut new_game, mut undo) = (false, false);
+        egui::Area::new(egui::Id::new("result_banner"))
+            .order(egui::Order::Foreground)
+            .fixed_pos(Pos2::new(386.5, 336.5)) // Center of the board
+            .pivot(egui::Align2::CENTER_CENTER)
+            .show(ctx, |ui| {
+                egui::Frame::popup(ui.style()).inner_margin(16.0).show(ui, |ui| {
+                    ui.vertical_centered(|ui| {
+                        ui.heading(headline);
+                        ui.label(reason);
+                        ui.horizontal(|ui| {
+                            new_game = ui.button("New game").clicked();
+                            undo = ui.add_enabled(self.record.can_undo(), egui::Button::new("Undo move")).clicked();
+                        });
+                    });
+                });
+            });
+        if new_game {
+            self.new_game();
+        }
+        if undo {
+            self.undo_move();
+        }
+    }
+
+    // Cancel searches before the position changes under the engines
+    fn stop_engines(&mut self) {
+        for engine in self.engines.iter_mut().flatten() {
+            engine.cancel();
+        }
+        // Engines would replay the move right away
+        self.paused = true;
+        self.game_over = None;
+    }
 }
 
-impl<'a> eframe::App for ShogiGame<'_> {
+impl eframe::App for ShogiGame<'_> {
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
+        egui::SidePanel::right("game_panel").min_width(260.0).show(ctx, |ui| {
+            self.render_game_panel(ui);
+        });
+
         CentralPanel::default().show(ctx, |ui| {
             egui::Frame::default()
                 .inner_margin(egui::Margin { left: 100.0, right: 100.0, top: 50.0, bottom: 50.0 })
                 .show(ui, |ui| {
+                    self.poll_engine();
+                    self.update_replay();
+                    self.update_game_mode();
+                    self.update_analysis();
+                    self.update_eval();
                     self.board.update_board(&self.pos);
                     self.render_pieces(ui);
                     self.render_grid(ui); 
 
                     ui.add_space(390.0);
                     ui.horizontal(|ui| {
-                        if ui.button(format!("Make Engine Move ({})", self.pos.side_to_move())).clicked() {
-                            self.make_engine_move();
+                        // In the other modes each side already has its player, engines move by themselves
+                        let idle = self.engine_idle(0) && self.game_over.is_none() && self.replay.is_none() && matches!(self.mode, GameMode::HumanVsHuman);
+                        if ui.add_enabled(idle, egui::Button::new(format!("Make Engine Move ({})", self.pos.side_to_move()))).clicked() {
+                            self.make_engine_move(0);
                         }
                         ui.label("Duration:");
                         ui.add(
-                            egui::TextEdit::singleline(&mut self.engine_ms)
+                            egui::TextEdit::singleline(&mut self.engine_ms[0])
                                 .desired_width(40.0)
                         );
                         ui.label("ms");
                     });
                     ui.horizontal(|ui| {
-                        if ui.button(format!("New game")).clicked() {
+                        match self.engines[0].as_ref() {
+                            Some(engine) => {
+                                let status = match engine.state {
+                                    EngineState::WaitingUsiOk   => "starting...",
+                                    EngineState::WaitingReadyOk => "initializing...",
+                                    EngineState::Ready          => "ready",
+                                    EngineState::Terminated     => "terminated",
+                                };
+                                ui.label(format!("Engine: {} ({})", engine.name, status));
+                            }
+                            None => {
+                                ui.label("Engine: none");
+                            }
+                        }
+                        if ui.button("Engine settings").clicked() {
+                            self.show_engine_settings = !self.show_engine_settings;
+                        }
+                        let loaded = self.engines.iter().any(|engine| engine.is_some());
+                        if ui.add_enabled(loaded, egui::Button::new("Engine options")).clicked() {
+                            self.show_engine_options = !self.show_engine_options;
+                        }
+                        if ui.add_enabled(self.engines[0].is_some(), egui::Button::new("Analysis")).clicked() {
+                            self.show_analysis = !self.show_analysis;
+                        }
+                        if ui.add_enabled(self.engines[0].is_some(), egui::SelectableLabel::new(self.analyze, "Analyze")).clicked() {
+                            self.analyze = !self.analyze;
+                            self.show_analysis |= self.analyze;
+                        }
+                    });
+                    if let Some(engine) = self.engines.iter_mut().flatten().find(|engine| engine.is_thinking()) {
+                        ui.horizontal(|ui| {
+                            ui.spinner();
+                            ui.label(format!("{} thinking... {:.1} s", engine.name, engine.elapsed().as_secs_f32()));
+                            if ui.button("Stop").clicked() {
+                                engine.stop();
+                            }
+                        });
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("New game").clicked() {
                             self.new_game();
                         }
-                        if ui.button(format!("Undo move")).clicked() {
+                        if ui.add_enabled(self.replay.is_none() && self.record.can_undo(), egui::Button::new("Undo move")).clicked() {
                             self.undo_move();
                         }
-                        if ui.button(format!("Promotion: {}", self.promotion_flag)).clicked() {
-                            self.promotion_flag = !self.promotion_flag;
+                        if ui.add_enabled(self.replay.is_none() && self.record.can_redo(), egui::Button::new("Redo move")).clicked() {
+                            self.redo_move();
+                        }
+                        let human_to_move = self.game_over.is_none() && self.replay.is_none() && !self.is_engine_turn() && !self.engine_thinking();
+                        if ui.add_enabled(human_to_move, egui::Button::new("Resign")).clicked() {
+                            self.resign();
+                        }
+                        // Only offered when the declaration would be accepted
+                        let declaration = rules::declaration(&self.pos, self.impasse_rule);
+                        let declare = ui.add_enabled(human_to_move && !matches!(declaration, Declaration::Invalid(_)), egui::Button::new("Declare win"));
+                        let declare = match declaration {
+                            Declaration::Invalid(reason) => declare.on_disabled_hover_text(format!("Cannot declare: {}", reason)),
+                            _ => declare,
+                        };
+                        if declare.clicked() {
+                            self.declare_win();
                         }
                     });
                     ui.horizontal(|ui| {
                         if ui.button("Print SFEN").clicked {
-                            println!("{}", self.pos.to_sfen());
+                            println!("{}", self.record.sfen());
                         }
                         if ui.button("Castle Presets").clicked() {
                             // TODO: egui-dropdown listing castles
                             self.new_game();
                             let castle_sfen = "lnsgkgsnl/1r5b1/ppppppppp/9/9/9/PPPPPPPPP/1B5R1/LNSGKGSNL b - 1 moves 2h7h 8b3b 5i4h 5a6b 4h3h 6b7b 3h2h 7b8b 3i3h 7a7b 6i5h 4a5b 1g1f";
-                            self.pos.set_sfen(castle_sfen).expect("Failed to set castle position.");
+                            self.record = GameRecord::from_sfen(castle_sfen).expect("Failed to set castle position.");
+                            self.sync_position();
                         }
                     });
                     if !self.error_message.is_empty() {
-                        ui.label(format!("{}", self.error_message));
+                        ui.label(&self.error_message);
                     }
 
                     ctx.request_repaint(); // Manual repaint for joystick location
                 });
         }); 
+
+        if self.show_engine_options {
+            self.render_engine_options(ctx);
+        }
+        if self.show_engine_settings {
+            self.render_engine_settings(ctx);
+        }
+        if self.show_analysis {
+            self.render_analysis(ctx);
+        }
+        if self.replay.is_none() {
+            self.render_result_banner(ctx);
+        }
     }
 }
\ No newline at end of file
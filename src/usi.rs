@@ -0,0 +1,225 @@
+// Parsing of USI protocol messages sent by the engine
+// http://shogidokoro.starfree.jp/usi.html
+
+use shogi::Move;
+
+use crate::rules;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum UsiOptionType {
+    Check,
+    Spin { min: i64, max: i64 },
+    Combo { vars: Vec<String> },
+    Button,
+    String,
+    Filename,
+}
+
+// One "option name ... type ..." line, value is what the user has entered in the options window
+#[derive(Clone, Debug)]
+pub struct UsiOption {
+    pub name: String,
+    pub kind: UsiOptionType,
+    pub default: String,
+    pub value: String,
+    pub applied: String, // Last value sent with setoption
+}
+
+impl UsiOption {
+    pub fn is_modified(&self) -> bool {
+        self.kind != UsiOptionType::Button && self.value != self.applied
+    }
+
+    pub fn setoption_command(&self) -> String {
+        match self.kind {
+            UsiOptionType::Button => format!("setoption name {}", self.name),
+            _ => format!("setoption name {} value {}", self.name, self.value),
+        }
+    }
+}
+
+// Parses "option name <id> type <t> [default <x>] [min <x>] [max <x>] [var <x>]*"
+// Names and values may contain spaces, so each field runs until the next keyword.
+pub fn parse_option(line: &str) -> Option<UsiOption> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() != Some("option") {
+        return None;
+    }
+
+    let mut fields: Vec<(&str, Vec<&str>)> = Vec::new();
+    for token in tokens {
+        match token {
+            "name" | "type" | "default" | "min" | "max" | "var" => fields.push((token, Vec::new())),
+            _ => fields.last_mut()?.1.push(token),
+        }
+    }
+
+    let field = |key: &str| fields.iter().find(|(k, _)| *k == key).map(|(_, v)| v.join(" "));
+
+    let name = field("name").filter(|name| !name.is_empty())?;
+    let mut default = field("default").unwrap_or_default();
+    if default == "<empty>" {
+        default.clear();
+    }
+
+    let kind = match field("type")?.as_str() {
+        "check"    => UsiOptionType::Check,
+        "spin"     => UsiOptionType::Spin {
+            min: field("min").and_then(|v| v.parse().ok()).unwrap_or(i64::MIN),
+            max: field("max").and_then(|v| v.parse().ok()).unwrap_or(i64::MAX),
+        },
+        "combo"    => UsiOptionType::Combo {
+            vars: fields.iter().filter(|(k, _)| *k == "var").map(|(_, v)| v.join(" ")).collect(),
+        },
+        "button"   => UsiOptionType::Button,
+        "string"   => UsiOptionType::String,
+        "filename" => UsiOptionType::Filename,
+        _ => return None,
+    };
+
+    Some(UsiOption {
+        name,
+        kind,
+        value: default.clone(),
+        applied: default.clone(),
+        default,
+    })
+}
+
+// Reply ending a search
+#[derive(Clone, Debug, PartialEq)]
+pub enum BestMove {
+    Move(Move),
+    Resign,
+    Win,             // Entering king declaration
+    NoMove,          // "bestmove none", sent by some engines that have no legal move
+    Invalid(String), // Whole line, for the error message
+}
+
+// Parses "bestmove <move> [ponder <move>]"
+pub fn parse_bestmove(line: &str) -> Option<BestMove> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() != Some("bestmove") {
+        return None;
+    }
+    Some(match tokens.next() {
+        Some("resign")          => BestMove::Resign,
+        Some("win")             => BestMove::Win,
+        Some("none" | "(none)") => BestMove::NoMove,
+        token => token.and_then(rules::parse_move).map_or(BestMove::Invalid(line.to_string()), BestMove::Move),
+    })
+}
+
+// Parses "id name <x>" and "id author <x>"
+pub fn parse_id(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix("id ")?;
+    let (key, value) = rest.split_once(' ')?;
+    Some((key, value.trim()))
+}
+
+// Evaluation from the side to move's point of view
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Score {
+    Cp(i32),
+    Mate(i32), // Plies to mate, negative when the side to move is getting mated
+}
+
+impl Score {
+    // Same evaluation from the other side's point of view
+    pub fn negate(self) -> Self {
+        match self {
+            Score::Cp(cp) => Score::Cp(-cp),
+            Score::Mate(n) => Score::Mate(-n),
+        }
+    }
+
+    // Expected score between 0 and 1 for the side the score belongs to, mates are clamped to 0 or 1
+    pub fn win_rate(self) -> f32 {
+        match self {
+            Score::Cp(cp) => (1.0 / (1.0 + (-cp as f32 / 600.0).exp())).clamp(0.02, 0.98),
+            Score::Mate(n) if n >= 0 => 1.0,
+            Score::Mate(_) => 0.0,
+        }
+    }
+}
+
+// "score mate +" / "score mate -" without a distance
+pub const MATE_UNKNOWN: i32 = i32::MAX;
+
+impl std::fmt::Display for Score {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Score::Cp(cp) => write!(f, "{:+}", cp),
+            Score::Mate(MATE_UNKNOWN) => write!(f, "Mate"),
+            Score::Mate(n) if n == -MATE_UNKNOWN => write!(f, "-Mate"),
+            Score::Mate(n) if n >= 0 => write!(f, "Mate {}", n),
+            Score::Mate(n) => write!(f, "-Mate {}", -n),
+        }
+    }
+}
+
+// Fields of "info ..." lines, engines may send any subset so lines are merged as they arrive
+#[derive(Clone, Debug, Default)]
+pub struct SearchInfo {
+    pub depth: Option<u32>,
+    pub seldepth: Option<u32>,
+    pub score: Option<Score>,
+    pub nodes: Option<u64>,
+    pub nps: Option<u64>,
+    pub time_ms: Option<u64>,
+    pub multipv: Option<usize>,
+    pub pv: Vec<String>,
+}
+
+impl SearchInfo {
+    pub fn merge(&mut self, other: SearchInfo) {
+        self.depth    = other.depth.or(self.depth);
+        self.seldepth = other.seldepth.or(self.seldepth);
+        self.score    = other.score.or(self.score);
+        self.nodes    = other.nodes.or(self.nodes);
+        self.nps      = other.nps.or(self.nps);
+        self.time_ms  = other.time_ms.or(self.time_ms);
+        self.multipv  = other.multipv.or(self.multipv);
+        if !other.pv.is_empty() {
+            self.pv = other.pv;
+        }
+    }
+}
+
+// Parses "info depth 10 seldepth 14 score cp 56 nodes 12345 nps 678 time 20 pv 7g7f 3c3d ..."
+// "info string" lines carry free text and are ignored.
+pub fn parse_info(line: &str) -> Option<SearchInfo> {
+    let mut tokens = line.split_whitespace();
+    if tokens.next() != Some("info") {
+        return None;
+    }
+
+    let mut info = SearchInfo::default();
+    while let Some(token) = tokens.next() {
+        match token {
+            "depth"    => info.depth    = tokens.next().and_then(|v| v.parse().ok()),
+            "seldepth" => info.seldepth = tokens.next().and_then(|v| v.parse().ok()),
+            "nodes"    => info.nodes    = tokens.next().and_then(|v| v.parse().ok()),
+            "nps"      => info.nps      = tokens.next().and_then(|v| v.parse().ok()),
+            "time"     => info.time_ms  = tokens.next().and_then(|v| v.parse().ok()),
+            "multipv"  => info.multipv  = tokens.next().and_then(|v| v.parse().ok()),
+            "score" => {
+                info.score = match (tokens.next(), tokens.next()) {
+                    (Some("cp"), Some(v)) => v.parse().ok().map(Score::Cp),
+                    (Some("mate"), Some("+")) => Some(Score::Mate(MATE_UNKNOWN)),
+                    (Some("mate"), Some("-")) => Some(Score::Mate(-MATE_UNKNOWN)),
+                    (Some("mate"), Some(v)) => v.parse().ok().map(Score::Mate),
+                    _ => None,
+                };
+            }
+            "lowerbound" | "upperbound" => (),
+            "pv" => {
+                info.pv = tokens.by_ref().map(String::from).collect();
+            }
+            "string" => return None,
+            _ => (),
+        }
+    }
+
+    Some(info)
+}